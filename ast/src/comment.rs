@@ -0,0 +1,347 @@
+use std::fmt;
+
+use crate::{
+    formatter::Formatter, Empty, LValue, LocalRw, RValue, RcLocal, Reduce, SideEffects, Statement,
+    Traverse,
+};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Comment {
+    pub text: String,
+}
+
+impl Comment {
+    pub fn new(text: String) -> Self {
+        Self { text }
+    }
+
+    // enough `=` that the text can't close a long comment
+    fn level(&self) -> String {
+        (0..)
+            .map(|n| "=".repeat(n))
+            .find(|level| !self.text.contains(&format!("]{}]", level)))
+            .unwrap()
+    }
+
+    /// Formats the comment so that code can follow it on the same line.
+    pub fn inline(&self) -> String {
+        let level = self.level();
+        format!("--[{}[ {} ]{}]", level, self.text, level)
+    }
+}
+
+impl Traverse for Comment {}
+
+impl SideEffects for Comment {}
+
+impl LocalRw for Comment {}
+
+impl fmt::Display for Comment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.text.contains('\n') {
+            let level = self.level();
+            write!(f, "--[{}[ {}\n]{}]", level, self.text, level)
+        } else {
+            write!(f, "-- {}", self.text)
+        }
+    }
+}
+
+// an expression with comments before and after it, `--[[ leading ]] value --[[ trailing ]]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Commented {
+    pub value: Box<RValue>,
+    pub leading: Vec<Comment>,
+    pub trailing: Vec<Comment>,
+}
+
+impl Commented {
+    pub fn new(value: RValue, leading: Vec<Comment>, trailing: Vec<Comment>) -> Self {
+        Self {
+            value: Box::new(value),
+            leading,
+            trailing,
+        }
+    }
+
+    pub fn precedence(&self) -> usize {
+        self.value.precedence()
+    }
+}
+
+impl Reduce for Commented {
+    fn reduce(self) -> RValue {
+        Self {
+            value: Box::new(self.value.reduce()),
+            ..self
+        }
+        .into()
+    }
+
+    fn reduce_condition(self) -> RValue {
+        Self {
+            value: Box::new(self.value.reduce_condition()),
+            ..self
+        }
+        .into()
+    }
+}
+
+impl SideEffects for Commented {
+    fn has_side_effects(&self) -> bool {
+        self.value.has_side_effects()
+    }
+}
+
+impl Traverse for Commented {
+    fn rvalues_mut(&mut self) -> Vec<&mut RValue> {
+        vec![&mut self.value]
+    }
+
+    fn rvalues(&self) -> Vec<&RValue> {
+        vec![&self.value]
+    }
+}
+
+impl LocalRw for Commented {
+    fn values_read(&self) -> Vec<&RcLocal> {
+        self.value.values_read()
+    }
+
+    fn values_read_mut(&mut self) -> Vec<&mut RcLocal> {
+        self.value.values_read_mut()
+    }
+}
+
+impl fmt::Display for Commented {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Formatter::new(f).format_commented(self)
+    }
+}
+
+// a statement with comments on their own lines before it and at the end of its line
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommentedStatement {
+    pub statement: Box<Statement>,
+    pub leading: Vec<Comment>,
+    pub trailing: Vec<Comment>,
+}
+
+impl CommentedStatement {
+    pub fn new(statement: Statement, leading: Vec<Comment>, trailing: Vec<Comment>) -> Self {
+        Self {
+            statement: Box::new(statement),
+            leading,
+            trailing,
+        }
+    }
+}
+
+impl SideEffects for CommentedStatement {
+    fn has_side_effects(&self) -> bool {
+        self.statement.has_side_effects()
+    }
+}
+
+impl Traverse for CommentedStatement {
+    fn lvalues_mut(&mut self) -> Vec<&mut LValue> {
+        self.statement.lvalues_mut()
+    }
+
+    fn rvalues_mut(&mut self) -> Vec<&mut RValue> {
+        self.statement.rvalues_mut()
+    }
+
+    fn rvalues(&self) -> Vec<&RValue> {
+        self.statement.rvalues()
+    }
+}
+
+impl LocalRw for CommentedStatement {
+    fn values_read(&self) -> Vec<&RcLocal> {
+        self.statement.values_read()
+    }
+
+    fn values_read_mut(&mut self) -> Vec<&mut RcLocal> {
+        self.statement.values_read_mut()
+    }
+
+    fn values_written(&self) -> Vec<&RcLocal> {
+        self.statement.values_written()
+    }
+
+    fn values_written_mut(&mut self) -> Vec<&mut RcLocal> {
+        self.statement.values_written_mut()
+    }
+}
+
+impl fmt::Display for CommentedStatement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for comment in &self.leading {
+            writeln!(f, "{}", comment)?;
+        }
+        write!(f, "{}", self.statement)?;
+        for comment in &self.trailing {
+            write!(f, " {}", comment)?;
+        }
+        Ok(())
+    }
+}
+
+impl Statement {
+    /// The statement without the comments attached to it.
+    pub fn inner(&self) -> &Statement {
+        match self {
+            Statement::Commented(commented) => &commented.statement,
+            statement => statement,
+        }
+    }
+
+    pub fn inner_mut(&mut self) -> &mut Statement {
+        match self {
+            Statement::Commented(commented) => &mut commented.statement,
+            statement => statement,
+        }
+    }
+
+    /// The statement without the comments attached to it, discarding them.
+    pub fn into_inner(self) -> Statement {
+        match self {
+            Statement::Commented(commented) => *commented.statement,
+            statement => statement,
+        }
+    }
+}
+
+// `statement`, wrapped in a `CommentedStatement` if it isn't one already
+fn commented(statement: &mut Statement) -> &mut CommentedStatement {
+    if !matches!(statement, Statement::Commented(_)) {
+        let inner = std::mem::replace(statement, Empty {}.into());
+        *statement = CommentedStatement::new(inner, Vec::new(), Vec::new()).into();
+    }
+    statement.as_commented_mut().unwrap()
+}
+
+/// Attaches `comment` to `statement` on its own line before it.
+pub fn comment_before(statement: &mut Statement, comment: Comment) {
+    commented(statement).leading.push(comment);
+}
+
+/// Attaches `comment` to `statement` at the end of its line.
+pub fn comment_after(statement: &mut Statement, comment: Comment) {
+    commented(statement).trailing.push(comment);
+}
+
+#[cfg(test)]
+mod tests {
+    use parking_lot::Mutex;
+    use rustc_hash::FxHashSet;
+    use triomphe::Arc;
+
+    use super::*;
+    use crate::{
+        local_declarations::LocalDeclarer, name_locals::name_locals, Assign, Block, Call, Global,
+        If, Literal, Local,
+    };
+
+    fn global(name: &str) -> RValue {
+        Global::new(name.as_bytes().to_vec()).into()
+    }
+
+    fn call(value: RValue) -> Statement {
+        Call::new(value, Vec::new()).into()
+    }
+
+    #[test]
+    fn comments_around_statement() {
+        let mut statement = call(global("f"));
+        comment_before(&mut statement, Comment::new("a".to_string()));
+        comment_before(&mut statement, Comment::new("b\nc".to_string()));
+        comment_after(&mut statement, Comment::new("d".to_string()));
+        let block = Block(vec![statement, call(global("g"))]);
+        assert_eq!(block.to_string(), "-- a\n--[[ b\nc\n]]\nf() -- d\ng()");
+    }
+
+    #[test]
+    fn semicolon_before_trailing_comment() {
+        let mut statement = call(global("f"));
+        comment_after(&mut statement, Comment::new("a".to_string()));
+        let mut next = call(Literal::String(b"s".to_vec()).into());
+        comment_before(&mut next, Comment::new("b".to_string()));
+        let block = Block(vec![statement, next]);
+        assert_eq!(block.to_string(), "f(); -- a\n-- b\n(\"s\")()");
+    }
+
+    #[test]
+    fn semicolon_after_commented_value() {
+        let value = Commented::new(global("f"), Vec::new(), vec![Comment::new("a".to_string())]);
+        let block = Block(vec![
+            Assign::new(vec![Global::new(b"x".to_vec()).into()], vec![value.into()]).into(),
+            call(Literal::String(b"s".to_vec()).into()),
+        ]);
+        assert_eq!(block.to_string(), "x = f --[[ a ]];\n(\"s\")()");
+    }
+
+    #[test]
+    fn declaration_keeps_comments_with_statement() {
+        let local = RcLocal::new(Local::new(Some("x".to_string())));
+        let mut statement: Statement = Assign::new(
+            vec![local.clone().into()],
+            vec![Literal::Number(1.0).into()],
+        )
+        .into();
+        comment_before(&mut statement, Comment::new("a".to_string()));
+        comment_after(&mut statement, Comment::new("b".to_string()));
+        let block = Arc::new(Mutex::new(Block(vec![
+            call(global("f")),
+            statement,
+            Call::new(global("g"), vec![local.into()]).into(),
+        ])));
+        LocalDeclarer::default().declare_locals(block.clone(), &FxHashSet::default());
+        assert_eq!(
+            block.lock().to_string(),
+            "f()\n-- a\nlocal x = 1 -- b\ng(x)"
+        );
+    }
+
+    #[test]
+    fn commented_else_if() {
+        let mut else_if: Statement = If::new(
+            global("b"),
+            Block(vec![call(global("g"))]),
+            Block::default(),
+        )
+        .into();
+        comment_before(&mut else_if, Comment::new("c".to_string()));
+        comment_after(&mut else_if, Comment::new("d".to_string()));
+        let block = Block(vec![If::new(
+            global("a"),
+            Block(vec![call(global("f"))]),
+            Block(vec![else_if]),
+        )
+        .into()]);
+        assert_eq!(
+            block.to_string(),
+            "if a then\n\tf()\n-- c\nelseif b then\n\tg()\nend -- d"
+        );
+    }
+
+    #[test]
+    fn commented_declaration_is_named() {
+        let local = RcLocal::new(Local::default());
+        let mut statement: Statement = Assign::new(
+            vec![local.clone().into()],
+            vec![Literal::Number(1.0).into()],
+        )
+        .into();
+        comment_before(&mut statement, Comment::new("a".to_string()));
+        let block = Arc::new(Mutex::new(Block(vec![
+            statement,
+            Call::new(global("f"), vec![local.into()]).into(),
+        ])));
+        LocalDeclarer::default().declare_locals(block.clone(), &FxHashSet::default());
+        let mut block = Arc::try_unwrap(block).unwrap().into_inner();
+        name_locals(&mut block, false, false);
+        assert_eq!(block.to_string(), "-- a\nlocal v1 = 1\nf(v1)");
+    }
+}
@@ -0,0 +1,235 @@
+use std::fmt;
+
+use crate::{
+    formatter::Formatter, Assign, Binary, BinaryOperation, Block, LValue, LocalRw, RValue, RcLocal,
+    SideEffects, Statement, Traverse,
+};
+
+// luau's `a += b`, `a ..= b`, etc.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompoundAssign {
+    pub left: LValue,
+    pub right: RValue,
+    pub operation: BinaryOperation,
+}
+
+impl CompoundAssign {
+    pub fn new(left: LValue, right: RValue, operation: BinaryOperation) -> Self {
+        Self {
+            left,
+            right,
+            operation,
+        }
+    }
+
+    fn is_compound(operation: BinaryOperation) -> bool {
+        matches!(
+            operation,
+            BinaryOperation::Add
+                | BinaryOperation::Sub
+                | BinaryOperation::Mul
+                | BinaryOperation::Div
+                | BinaryOperation::IDiv
+                | BinaryOperation::Mod
+                | BinaryOperation::Pow
+                | BinaryOperation::Concat
+        )
+    }
+
+    // the target is evaluated once by a compound assignment and twice by `a = a + b`,
+    // so only a local, a global or a single index into a local table with a local or
+    // constant key is allowed. nothing in those can change between the evaluations
+    fn is_simple_target(lvalue: &LValue) -> bool {
+        match lvalue {
+            LValue::Local(_) | LValue::Global(_) => true,
+            LValue::Index(index) => {
+                matches!(*index.left, RValue::Local(_))
+                    && matches!(*index.right, RValue::Local(_) | RValue::Literal(_))
+            }
+        }
+    }
+
+    // `a = a + b` -> `a += b`
+    fn from_assign(assign: &Assign) -> Option<Self> {
+        if assign.prefix || assign.left.len() != 1 || assign.right.len() != 1 {
+            return None;
+        }
+        let left = &assign.left[0];
+        let RValue::Binary(Binary {
+            left: box binary_left,
+            right: box binary_right,
+            operation,
+        }) = &assign.right[0]
+        else {
+            return None;
+        };
+        if !Self::is_compound(*operation) || !Self::is_simple_target(left) {
+            return None;
+        }
+        let is_target = match (left, binary_left) {
+            (LValue::Local(left), RValue::Local(right)) => left == right,
+            (LValue::Global(left), RValue::Global(right)) => left == right,
+            (LValue::Index(left), RValue::Index(right)) => left == right,
+            _ => false,
+        };
+        if !is_target {
+            return None;
+        }
+        Some(Self::new(left.clone(), binary_right.clone(), *operation))
+    }
+}
+
+impl Traverse for CompoundAssign {
+    fn lvalues_mut(&mut self) -> Vec<&mut LValue> {
+        vec![&mut self.left]
+    }
+
+    fn rvalues_mut(&mut self) -> Vec<&mut RValue> {
+        vec![&mut self.right]
+    }
+
+    fn rvalues(&self) -> Vec<&RValue> {
+        vec![&self.right]
+    }
+}
+
+impl SideEffects for CompoundAssign {
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+}
+
+impl LocalRw for CompoundAssign {
+    // the target is read before it is written
+    fn values_read(&self) -> Vec<&RcLocal> {
+        self.left
+            .as_local()
+            .into_iter()
+            .chain(self.left.values_read())
+            .chain(self.right.values_read())
+            .collect()
+    }
+
+    fn values_read_mut(&mut self) -> Vec<&mut RcLocal> {
+        match &mut self.left {
+            LValue::Local(local) => std::iter::once(local)
+                .chain(self.right.values_read_mut())
+                .collect(),
+            left => left
+                .values_read_mut()
+                .into_iter()
+                .chain(self.right.values_read_mut())
+                .collect(),
+        }
+    }
+
+    fn values_written(&self) -> Vec<&RcLocal> {
+        self.left.values_written()
+    }
+
+    fn values_written_mut(&mut self) -> Vec<&mut RcLocal> {
+        self.left.values_written_mut()
+    }
+}
+
+impl fmt::Display for CompoundAssign {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Formatter::new(f).format_compound_assign(self)
+    }
+}
+
+/// Replaces `a = a + b` with `a += b` in `block` and the blocks nested in it.
+/// Declarations are left alone, so this should run after locals are declared.
+pub fn fold_compound_assignments(block: &mut Block) {
+    for statement in block.iter_mut() {
+        let statement = statement.inner_mut();
+        match statement {
+            Statement::Assign(assign) => {
+                if let Some(compound_assign) = CompoundAssign::from_assign(assign) {
+                    *statement = compound_assign.into();
+                }
+            }
+            Statement::If(r#if) => {
+                fold_compound_assignments(&mut r#if.then_block.lock());
+                fold_compound_assignments(&mut r#if.else_block.lock());
+            }
+            Statement::While(r#while) => fold_compound_assignments(&mut r#while.block.lock()),
+            Statement::Repeat(repeat) => fold_compound_assignments(&mut repeat.block.lock()),
+            Statement::NumericFor(numeric_for) => {
+                fold_compound_assignments(&mut numeric_for.block.lock())
+            }
+            Statement::GenericFor(generic_for) => {
+                fold_compound_assignments(&mut generic_for.block.lock())
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Global, Index, Literal, Local};
+
+    fn local(name: &str) -> RcLocal {
+        RcLocal::new(Local::new(Some(name.to_string())))
+    }
+
+    fn index(left: RValue, key: &str) -> Index {
+        Index::new(left, Literal::String(key.as_bytes().to_vec()).into())
+    }
+
+    // `target = target + operand`
+    fn fold(target: RValue, operand: RValue) -> String {
+        let left = match target.clone() {
+            RValue::Local(local) => local.into(),
+            RValue::Global(global) => global.into(),
+            RValue::Index(index) => index.into(),
+            _ => unreachable!(),
+        };
+        let binary = Binary::new(target, operand, BinaryOperation::Add);
+        let mut block = Block(vec![Assign::new(vec![left], vec![binary.into()]).into()]);
+        fold_compound_assignments(&mut block);
+        block.to_string()
+    }
+
+    #[test]
+    fn local_target() {
+        let x = local("x");
+        assert_eq!(fold(x.into(), Literal::Number(1.0).into()), "x += 1");
+    }
+
+    #[test]
+    fn global_target() {
+        let x = Global::new(b"x".to_vec());
+        assert_eq!(fold(x.into(), Literal::Number(1.0).into()), "x += 1");
+    }
+
+    #[test]
+    fn index_target() {
+        let (t, k) = (local("t"), local("k"));
+        let target = index(t.clone().into(), "x");
+        assert_eq!(fold(target.into(), Literal::Number(1.0).into()), "t.x += 1");
+        let target = Index::new(t.into(), k.into());
+        assert_eq!(
+            fold(target.into(), Literal::Number(1.0).into()),
+            "t[k] += 1"
+        );
+    }
+
+    #[test]
+    fn nested_index_target_rejected() {
+        // `t.a` could be changed by a metamethod between the two evaluations of `t.a.x`
+        let t = local("t");
+        let target = index(index(t.into(), "a").into(), "x");
+        assert_eq!(
+            fold(target.into(), Literal::Number(1.0).into()),
+            "t.a.x = t.a.x + 1"
+        );
+        let target = index(Global::new(b"T".to_vec()).into(), "x");
+        assert_eq!(
+            fold(target.into(), Literal::Number(1.0).into()),
+            "T.x = T.x + 1"
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+
+use crate::{BinaryOperation, Block, LValue, RValue, Statement, Traverse};
+
+/// The language the formatter writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    Lua51,
+    #[default]
+    Luau,
+}
+
+impl Dialect {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dialect::Lua51 => "Lua 5.1",
+            Dialect::Luau => "Luau",
+        }
+    }
+
+    pub fn supports_continue(&self) -> bool {
+        *self == Dialect::Luau
+    }
+
+    pub fn supports_compound_assign(&self) -> bool {
+        *self == Dialect::Luau
+    }
+
+    pub fn supports_if_expression(&self) -> bool {
+        *self == Dialect::Luau
+    }
+
+    pub fn supports_interpolated_string(&self) -> bool {
+        *self == Dialect::Luau
+    }
+
+    pub fn supports_floor_division(&self) -> bool {
+        *self == Dialect::Luau
+    }
+
+    pub fn supports_unicode_escape(&self) -> bool {
+        *self == Dialect::Luau
+    }
+
+    /// Finds every construct in `block`, including nested blocks and closures,
+    /// that can't be written in this dialect.
+    pub fn validate(self, block: &Block) -> Vec<Unsupported> {
+        let mut validator = Validator {
+            dialect: self,
+            unsupported: Vec::new(),
+        };
+        validator.visit_block(block);
+        validator.unsupported
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A construct that can't be written in the dialect it was validated against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unsupported {
+    pub construct: &'static str,
+    pub dialect: Dialect,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is not supported by {}", self.construct, self.dialect)
+    }
+}
+
+struct Validator {
+    dialect: Dialect,
+    unsupported: Vec<Unsupported>,
+}
+
+impl Validator {
+    fn report(&mut self, construct: &'static str) {
+        self.unsupported.push(Unsupported {
+            construct,
+            dialect: self.dialect,
+        });
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        for statement in block.iter() {
+            self.visit_statement(statement);
+        }
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        let statement = statement.inner();
+        match statement {
+            // neither dialect has goto
+            Statement::Goto(_) => self.report("goto"),
+            Statement::Label(_) => self.report("label"),
+            Statement::Continue(_) if !self.dialect.supports_continue() => self.report("continue"),
+            Statement::CompoundAssign(_) if !self.dialect.supports_compound_assign() => {
+                self.report("compound assignment")
+            }
+            // only used while lifting, these should never reach the output
+            Statement::NumForInit(_)
+            | Statement::NumForNext(_)
+            | Statement::GenericForInit(_)
+            | Statement::GenericForNext(_)
+            | Statement::Close(_)
+            | Statement::SetList(_) => self.report("intermediate statement"),
+            _ => {}
+        }
+
+        match statement {
+            Statement::Assign(assign) => assign.left.iter().for_each(|l| self.visit_lvalue(l)),
+            Statement::CompoundAssign(compound_assign) => self.visit_lvalue(&compound_assign.left),
+            _ => {}
+        }
+        for rvalue in statement.rvalues() {
+            self.visit_rvalue(rvalue);
+        }
+
+        match statement {
+            Statement::If(r#if) => {
+                self.visit_block(&r#if.then_block.lock());
+                self.visit_block(&r#if.else_block.lock());
+            }
+            Statement::While(r#while) => self.visit_block(&r#while.block.lock()),
+            Statement::Repeat(repeat) => self.visit_block(&repeat.block.lock()),
+            Statement::NumericFor(numeric_for) => self.visit_block(&numeric_for.block.lock()),
+            Statement::GenericFor(generic_for) => self.visit_block(&generic_for.block.lock()),
+            _ => {}
+        }
+    }
+
+    fn visit_lvalue(&mut self, lvalue: &LValue) {
+        if let LValue::Index(index) = lvalue {
+            self.visit_rvalue(&index.left);
+            self.visit_rvalue(&index.right);
+        }
+    }
+
+    fn visit_rvalue(&mut self, rvalue: &RValue) {
+        match rvalue {
+            RValue::IfExpression(_) if !self.dialect.supports_if_expression() => {
+                self.report("if expression")
+            }
+            RValue::InterpolatedString(_) if !self.dialect.supports_interpolated_string() => {
+                self.report("interpolated string")
+            }
+            RValue::Binary(binary)
+                if binary.operation == BinaryOperation::IDiv
+                    && !self.dialect.supports_floor_division() =>
+            {
+                self.report("floor division")
+            }
+            RValue::Closure(closure) => self.visit_block(&closure.function.lock().body),
+            _ => {}
+        }
+        for rvalue in rvalue.rvalues() {
+            self.visit_rvalue(rvalue);
+        }
+    }
+}
@@ -7,11 +7,17 @@ use std::{
 
 use itertools::Itertools;
 
+mod doc;
+
+use doc::{Doc, Output};
+
 use crate::{
-    Assign, Binary, BinaryOperation, Block, Call, Closure, GenericFor, If, Index, LValue, Literal,
-    MethodCall, NumericFor, RValue, Repeat, Return, Select, Statement, Table, Unary, While,
+    Assign, Binary, BinaryOperation, Block, Call, Closure, Commented, CompoundAssign, Dialect,
+    GenericFor, If, IfExpression, Index, InterpolatedString, LValue, Literal, MethodCall,
+    NumericFor, RValue, Repeat, Return, Select, Statement, Table, Unary, Upvalue, While,
 };
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum IndentationMode {
     Spaces(u8),
     Tab,
@@ -42,6 +48,87 @@ impl Default for IndentationMode {
     }
 }
 
+/// The quote strings are written with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum QuoteStyle {
+    #[default]
+    Double,
+    Single,
+}
+
+impl QuoteStyle {
+    fn quote(&self) -> u8 {
+        match self {
+            QuoteStyle::Double => b'"',
+            QuoteStyle::Single => b'\'',
+        }
+    }
+
+    fn other(&self) -> Self {
+        match self {
+            QuoteStyle::Double => QuoteStyle::Single,
+            QuoteStyle::Single => QuoteStyle::Double,
+        }
+    }
+}
+
+/// How vector constants are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum VectorStyle {
+    /// `Vector3.new(x, y, z)`
+    #[default]
+    Roblox,
+    /// `vector.create(x, y, z)`, or `vector.create(x, y, z, w)` if w isn't 0
+    Library,
+}
+
+impl VectorStyle {
+    fn constructor(&self) -> &'static str {
+        match self {
+            VectorStyle::Roblox => "Vector3.new",
+            VectorStyle::Library => "vector.create",
+        }
+    }
+}
+
+/// The line width used when none is given.
+pub const DEFAULT_LINE_WIDTH: usize = 120;
+
+/// How the formatter lays out code.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FormatOptions {
+    pub indentation_mode: IndentationMode,
+    /// The preferred quote, the other one is used for strings that contain more of the preferred one.
+    pub quote_style: QuoteStyle,
+    /// Write a separator after the last field of tables that are broken across lines.
+    pub trailing_separators: bool,
+    /// End every statement with a semicolon, instead of only where it's ambiguous.
+    pub semicolons: bool,
+    /// Write integers that look like bit masks in hexadecimal when they are used with `bit32`.
+    pub hex_integers: bool,
+    /// Escape non-printable characters with `\u{...}` instead of escaping their bytes,
+    /// only in dialects that support it.
+    pub unicode_escapes: bool,
+    pub vector_style: VectorStyle,
+    /// The width lines are broken at where possible.
+    pub line_width: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            indentation_mode: Default::default(),
+            quote_style: Default::default(),
+            trailing_separators: false,
+            semicolons: false,
+            hex_integers: false,
+            unicode_escapes: false,
+            vector_style: Default::default(),
+            line_width: DEFAULT_LINE_WIDTH,
+        }
+    }
+}
+
 pub(crate) fn format_arg_list(list: &[RValue]) -> String {
     let mut s = String::new();
     for (index, rvalue) in list.iter().enumerate() {
@@ -60,39 +147,53 @@ pub(crate) fn format_arg_list(list: &[RValue]) -> String {
 
 pub struct Formatter<'a, W: fmt::Write> {
     pub(crate) indentation_level: usize,
-    pub(crate) indentation_mode: IndentationMode,
-    pub(crate) output: &'a mut W,
+    pub(crate) dialect: Dialect,
+    pub(crate) options: FormatOptions,
+    pub(crate) output: Output<'a, W>,
 }
 
 impl<'a, W: fmt::Write> Formatter<'a, W> {
+    pub(crate) fn new(output: &'a mut W) -> Self {
+        Self::with_options(output, Default::default(), Default::default())
+    }
+
+    pub fn with_options(output: &'a mut W, dialect: Dialect, options: FormatOptions) -> Self {
+        Self {
+            indentation_level: 0,
+            dialect,
+            options,
+            output: Output::new(output),
+        }
+    }
+
     pub fn format(
         main: &Block,
         output: &'a mut W,
-        indentation_mode: IndentationMode,
+        dialect: Dialect,
+        options: FormatOptions,
     ) -> fmt::Result {
-        let mut formatter = Self {
-            indentation_level: 0,
-            indentation_mode,
-            output,
-        };
-        formatter.format_block_no_indent(main)
+        Self::with_options(output, dialect, options).format_block_no_indent(main)
     }
 
     fn indent(&mut self) -> fmt::Result {
-        self.indentation_mode
+        self.options
+            .indentation_mode
             .display(&mut self.output, self.indentation_level)
     }
 
     // (function() end)()
     // (function() end)[1]
     fn should_wrap_left_rvalue(value: &RValue) -> bool {
-        !matches!(
-            value,
-            RValue::Local(_)
-                | RValue::Global(_)
-                | RValue::Index(_)
-                | RValue::Select(Select::Call(_) | Select::MethodCall(_))
-        )
+        match value {
+            RValue::Commented(commented) => Self::should_wrap_left_rvalue(&commented.value),
+            _ => !matches!(
+                value,
+                RValue::Local(_)
+                    | RValue::Global(_)
+                    | RValue::Index(_)
+                    | RValue::Select(Select::Call(_) | Select::MethodCall(_))
+            ),
+        }
     }
 
     fn format_block(&mut self, block: &Block) -> fmt::Result {
@@ -103,13 +204,31 @@ impl<'a, W: fmt::Write> Formatter<'a, W> {
     }
 
     fn format_block_no_indent(&mut self, block: &Block) -> fmt::Result {
+        // the comments attached to a statement don't change how it's separated from the next one
         for (i, statement) in block.iter().enumerate() {
             if i != 0 {
                 writeln!(self.output)?;
             }
+            let (statement, trailing) = match statement {
+                Statement::Commented(commented) => {
+                    for comment in &commented.leading {
+                        self.indent()?;
+                        writeln!(self.output, "{}", comment)?;
+                    }
+                    (commented.statement.as_ref(), commented.trailing.as_slice())
+                }
+                _ => (statement, [].as_slice()),
+            };
             self.format_statement(statement)?;
-            if let Some(next_statement) =
-                block.iter().skip(i + 1).find(|s| s.as_comment().is_none())
+            if self.options.semicolons {
+                if statement.as_comment().is_none() {
+                    write!(self.output, ";")?;
+                }
+            } else if let Some(next_statement) = block
+                .iter()
+                .skip(i + 1)
+                .find(|s| s.as_comment().is_none())
+                .map(Statement::inner)
             {
                 fn is_ambiguous(r: &RValue) -> bool {
                     match r {
@@ -120,6 +239,10 @@ impl<'a, W: fmt::Write> Formatter<'a, W> {
                         | RValue::MethodCall(_)
                         | RValue::Select(Select::Call(_) | Select::MethodCall(_)) => true,
                         RValue::Binary(binary) => is_ambiguous(&binary.right),
+                        RValue::IfExpression(if_expression) => {
+                            is_ambiguous(&if_expression.else_value)
+                        }
+                        RValue::Commented(commented) => is_ambiguous(&commented.value),
                         _ => false,
                     }
                 }
@@ -127,6 +250,9 @@ impl<'a, W: fmt::Write> Formatter<'a, W> {
                 let disambiguate = match statement {
                     Statement::Call(_) | Statement::MethodCall(_) => true,
                     Statement::Repeat(repeat) => is_ambiguous(&repeat.condition),
+                    Statement::CompoundAssign(compound_assign) => {
+                        is_ambiguous(&compound_assign.right)
+                    }
                     Statement::Assign(Assign { right: list, .. })
                     | Statement::Return(Return { values: list }) => {
                         if let Some(last) = list.last() {
@@ -151,25 +277,31 @@ impl<'a, W: fmt::Write> Formatter<'a, W> {
                                 false
                             }
                         }
+                        Statement::CompoundAssign(CompoundAssign {
+                            left: LValue::Index(index),
+                            ..
+                        }) => Self::should_wrap_left_rvalue(&index.left),
                         Statement::Call(Call { value, .. })
                         | Statement::MethodCall(MethodCall { value, .. }) => {
                             Self::should_wrap_left_rvalue(value)
                         }
-                        Statement::Comment(_) => unimplemented!(),
                         _ => false,
                     };
                 if disambiguate {
                     write!(self.output, ";")?;
                 }
             }
+            for comment in trailing {
+                write!(self.output, " {}", comment)?;
+            }
         }
         Ok(())
     }
 
-    fn format_lvalue(&mut self, lvalue: &LValue) -> fmt::Result {
+    fn lvalue_doc<'b>(&self, lvalue: &'b LValue) -> Doc<'b> {
         match lvalue {
-            LValue::Index(index) => self.format_index(index),
-            _ => write!(self.output, "{}", lvalue),
+            LValue::Index(index) => self.index_doc(index),
+            _ => lvalue.to_string().into(),
         }
     }
 
@@ -199,102 +331,115 @@ impl<'a, W: fmt::Write> Formatter<'a, W> {
     }
 
     pub(crate) fn format_table(&mut self, table: &Table) -> fmt::Result {
-        let sequential_keys = Self::are_table_keys_sequential(table);
-        let should_space = !table.0.is_empty();
-        let should_format = !table.0.is_empty() && (!sequential_keys || table.0.len() > 3)
-            || Self::contains_table(table);
-        write!(self.output, "{{")?;
-        if should_format {
-            writeln!(self.output)?;
-        } else if should_space {
-            write!(self.output, " ")?;
+        let doc = self.table_doc(table);
+        self.write_doc(&doc)
+    }
+
+    fn table_doc<'b>(&self, table: &'b Table) -> Doc<'b> {
+        if table.0.is_empty() {
+            return "{}".into();
         }
-        self.indentation_level += 1;
+        let sequential_keys = Self::are_table_keys_sequential(table);
+        let broken = !sequential_keys || table.0.len() > 3 || Self::contains_table(table);
+        let mut fields = vec![Doc::Line];
         for (index, (key, value)) in table.0.iter().enumerate() {
-            if should_format {
-                self.indent()?;
+            if index != 0 {
+                fields.push(",".into());
+                fields.push(Doc::Line);
             }
             let is_last = index + 1 == table.0.len();
             if is_last && key.is_none() {
                 let wrap = matches!(value, RValue::Select(_));
-                if wrap {
-                    write!(self.output, "(")?;
-                }
-                self.format_rvalue(value)?;
-                if wrap {
-                    write!(self.output, ")")?;
-                }
+                fields.push(Doc::parenthesize(wrap, self.rvalue_doc(value)));
             } else {
-                if !sequential_keys {
-                    if let Some(key) = key {
-                        write!(self.output, "[")?;
-                        self.format_rvalue(key)?;
-                        write!(self.output, "] = ")?;
-                    }
-                }
-                self.format_rvalue(value)?;
-                if !is_last {
-                    write!(self.output, ",")?;
-                    write!(self.output, "{}", if should_format { "\n" } else { " " })?;
+                if !sequential_keys && let Some(key) = key {
+                    fields.push("[".into());
+                    fields.push(self.key_doc(key));
+                    fields.push("] = ".into());
                 }
+                fields.push(self.rvalue_doc(value));
             }
         }
-        self.indentation_level -= 1;
-        if should_format {
-            writeln!(self.output)?;
-            self.indent()?;
-        } else if should_space {
-            write!(self.output, " ")?;
+        if self.options.trailing_separators {
+            fields.push(Doc::if_broken(",".into()));
+        }
+        let doc = vec!["{".into(), Doc::nest(fields.into()), Doc::Line, "}".into()].into();
+        if broken {
+            Doc::broken_group(doc)
+        } else {
+            Doc::group(doc)
         }
-        write!(self.output, "}}")
     }
 
-    pub(crate) fn format_unary(&mut self, unary: &Unary) -> fmt::Result {
-        write!(self.output, "{}", unary.operation)?;
-        let wrap = unary.group();
-        if wrap {
-            write!(self.output, "(")?;
-        }
-        self.format_rvalue(&unary.value)?;
-        if wrap {
-            write!(self.output, ")")?;
-        }
-        Ok(())
+    fn unary_doc<'b>(&self, unary: &'b Unary) -> Doc<'b> {
+        vec![
+            unary.operation.to_string().into(),
+            Doc::parenthesize(unary.group(), self.rvalue_doc(&unary.value)),
+        ]
+        .into()
     }
 
-    pub(crate) fn format_binary(&mut self, binary: &Binary) -> fmt::Result {
-        let parentheses = |f: &mut Self, wrap: bool, rvalue: &RValue| -> fmt::Result {
-            if wrap {
-                write!(f.output, "(")?;
-            }
-            f.format_rvalue(rvalue)?;
-            if wrap {
-                write!(f.output, ")")?;
+    fn binary_doc<'b>(&self, binary: &'b Binary) -> Doc<'b> {
+        if matches!(binary.operation, BinaryOperation::And | BinaryOperation::Or) {
+            // `a and b and c` is `(a and b) and c`, the whole chain is broken at once
+            let mut operands = Vec::new();
+            let mut chain = binary;
+            while let RValue::Binary(left) = chain.left.as_ref()
+                && left.operation == binary.operation
+                && !chain.left_group()
+            {
+                operands.push(Doc::parenthesize(
+                    chain.right_group(),
+                    self.rvalue_doc(&chain.right),
+                ));
+                chain = left;
             }
-            Ok(())
-        };
+            operands.push(Doc::parenthesize(
+                chain.right_group(),
+                self.rvalue_doc(&chain.right),
+            ));
+            let first = Doc::parenthesize(chain.left_group(), self.rvalue_doc(&chain.left));
+            let rest = operands
+                .into_iter()
+                .rev()
+                .flat_map(|operand| [Doc::Line, format!("{} ", binary.operation).into(), operand])
+                .collect_vec();
+            return Doc::group(vec![first, Doc::nest(rest.into())].into());
+        }
 
-        parentheses(self, binary.left_group(), &binary.left)?;
-        write!(self.output, " {} ", binary.operation)?;
-        parentheses(self, binary.right_group(), &binary.right)
+        // `bit32.band(a, 0xFF00) == 0x8000`
+        let bit_masks = binary.operation.is_comparator()
+            && (Self::is_bit32_call(&binary.left) || Self::is_bit32_call(&binary.right));
+        vec![
+            Doc::parenthesize(
+                binary.left_group(),
+                self.operand_doc(&binary.left, bit_masks),
+            ),
+            format!(" {} ", binary.operation).into(),
+            Doc::parenthesize(
+                binary.right_group(),
+                self.operand_doc(&binary.right, bit_masks),
+            ),
+        ]
+        .into()
     }
 
-    fn format_closure_parameters(&mut self, closure: &Closure) -> fmt::Result {
+    // the parameters of `closure`, without `self` for a method
+    fn closure_parameters(closure: &Closure, method: bool) -> String {
         let function = closure.function.lock();
-        write!(
-            self.output,
-            "{}",
-            if function.is_variadic {
-                function
-                    .parameters
-                    .iter()
-                    .map(|x| x.to_string())
-                    .chain(std::iter::once("...".into()))
-                    .join(", ")
-            } else {
-                function.parameters.iter().join(", ")
-            }
-        )
+        let mut parameters = function.parameters.iter().skip(method as usize);
+        if function.is_variadic {
+            parameters
+                .map(|x| x.to_string())
+                .chain(std::iter::once("...".into()))
+                .join(", ")
+        } else {
+            parameters.join(", ")
+        }
+    }
+
+    pub(crate) fn closure_header(closure: &Closure) -> String {
+        format!("function({})", Self::closure_parameters(closure, false))
     }
 
     fn format_closure_body(&mut self, closure: &Closure) -> fmt::Result {
@@ -339,77 +484,241 @@ impl<'a, W: fmt::Write> Formatter<'a, W> {
         }
     }
 
-    pub(crate) fn format_closure(&mut self, closure: &Closure) -> fmt::Result {
-        write!(self.output, "function(")?;
-        self.format_closure_parameters(closure)?;
-        write!(self.output, ")")?;
+    pub fn format_closure(&mut self, closure: &Closure) -> fmt::Result {
+        write!(self.output, "{}", Self::closure_header(closure))?;
         self.format_closure_body(closure)?;
         write!(self.output, "end")
     }
 
-    fn format_named_function(&mut self, name: &LValue, closure: &Closure) -> fmt::Result {
-        write!(self.output, "function {}(", name)?;
-        self.format_closure_parameters(closure)?;
-        write!(self.output, ")")?;
+    fn format_named_function(
+        &mut self,
+        name: &str,
+        closure: &Closure,
+        method: bool,
+    ) -> fmt::Result {
+        write!(
+            self.output,
+            "function {}({})",
+            name,
+            Self::closure_parameters(closure, method)
+        )?;
         self.format_closure_body(closure)?;
         write!(self.output, "end")
     }
 
     fn format_rvalue(&mut self, rvalue: &RValue) -> fmt::Result {
+        let doc = self.rvalue_doc(rvalue);
+        self.write_doc(&doc)
+    }
+
+    fn rvalue_doc<'b>(&self, rvalue: &'b RValue) -> Doc<'b> {
         match rvalue {
-            RValue::Select(Select::Call(call)) | RValue::Call(call) => self.format_call(call),
+            RValue::Select(Select::Call(call)) | RValue::Call(call) => self.call_doc(call),
             RValue::Select(Select::MethodCall(method_call)) | RValue::MethodCall(method_call) => {
-                self.format_method_call(method_call)
+                self.method_call_doc(method_call)
             }
-            RValue::Table(table) => self.format_table(table),
-            RValue::Index(index) => self.format_index(index),
-            RValue::Unary(unary) => self.format_unary(unary),
-            RValue::Binary(binary) => self.format_binary(binary),
-            RValue::Closure(closure) => self.format_closure(closure),
-            RValue::Literal(Literal::Number(n)) if n.is_infinite() => {
-                // TODO: only insert parentheses when necessary
-                write!(self.output, "(")?;
-                self.format_binary(&Binary::new(
-                    Literal::Number(if n.is_sign_positive() { 1.0 } else { -1.0 }).into(),
-                    Literal::Number(0.0).into(),
-                    BinaryOperation::Div,
-                ))?;
-                write!(self.output, ")")
+            RValue::Table(table) => self.table_doc(table),
+            RValue::Index(index) => self.index_doc(index),
+            RValue::Unary(unary) => self.unary_doc(unary),
+            RValue::Binary(binary) => self.binary_doc(binary),
+            RValue::Closure(closure) => Doc::Closure(closure),
+            RValue::IfExpression(if_expression) => self.if_expression_doc(if_expression),
+            RValue::InterpolatedString(interpolated_string) => {
+                self.interpolated_string_doc(interpolated_string)
             }
-            RValue::Literal(Literal::Number(n)) if n.is_nan() => {
-                // TODO: check that nan is appropriate for platform
-                // assert_eq!(n.to_bits(), 0x7ff8000000000000);
-                // TODO: only insert parentheses when necessary
-                write!(self.output, "(")?;
-                self.format_binary(&Binary::new(
-                    Literal::Number(0.0).into(),
-                    Literal::Number(0.0).into(),
-                    BinaryOperation::Div,
-                ))?;
-                write!(self.output, ")")
+            RValue::Literal(literal) => self.literal_doc(literal),
+            RValue::Commented(commented) => self.commented_doc(commented),
+            _ => rvalue.to_string().into(),
+        }
+    }
+
+    pub(crate) fn format_literal(&mut self, literal: &Literal) -> fmt::Result {
+        let doc = self.literal_doc(literal);
+        self.write_doc(&doc)
+    }
+
+    fn literal_doc<'b>(&self, literal: &Literal) -> Doc<'b> {
+        match *literal {
+            Literal::Number(n) => Self::number(n).into(),
+            Literal::String(ref string) => Self::long_string(string)
+                .unwrap_or_else(|| self.quote_string(string))
+                .into(),
+            Literal::Nil => "nil".into(),
+            Literal::Boolean(value) => value.to_string().into(),
+            Literal::Vector(x, y, z, w) => {
+                let components = match self.options.vector_style {
+                    // roblox vectors have no w component
+                    VectorStyle::Roblox => vec![x, y, z],
+                    VectorStyle::Library if w != 0.0 || w.is_sign_negative() => vec![x, y, z, w],
+                    VectorStyle::Library => vec![x, y, z],
+                };
+                format!(
+                    "{}({})",
+                    self.options.vector_style.constructor(),
+                    components.into_iter().map(Self::float).join(", ")
+                )
+                .into()
             }
-            _ => write!(self.output, "{}", rvalue),
         }
     }
 
-    fn format_arg_list(&mut self, list: &[RValue]) -> fmt::Result {
-        for (index, rvalue) in list.iter().enumerate() {
-            if index + 1 == list.len() {
-                let wrap = matches!(rvalue, RValue::Select(_));
-                if wrap {
-                    write!(self.output, "(")?;
-                }
-                self.format_rvalue(rvalue)?;
-                if wrap {
-                    write!(self.output, ")")?;
-                }
+    // the shortest text that is read as exactly `n`.
+    // see `RValue::precedence` for when it has to be parenthesized.
+    fn number(n: f64) -> String {
+        if n.is_infinite() {
+            if n.is_sign_positive() {
+                "1 / 0".into()
+            } else {
+                "-1 / 0".into()
+            }
+        } else if n.is_nan() {
+            // `0 / 0` is a negative nan on x86, the payload of a nan is lost
+            if n.is_sign_negative() {
+                "0 / 0".into()
             } else {
-                self.format_rvalue(rvalue)?;
-                write!(self.output, ", ")?;
+                "-(0 / 0)".into()
             }
+        } else if n.fract() == 0.0 && n.abs() < 1e21 {
+            // integers are written without an exponent, `-0` keeps its sign
+            n.to_string()
+        } else {
+            // TODO: fork ryu to remove ".0"
+            let mut buffer = ryu::Buffer::new();
+            let printed = buffer.format_finite(n);
+            printed.strip_suffix(".0").unwrap_or(printed).to_string()
         }
-        Ok(())
     }
+
+    // the shortest text that is read as exactly `n` once it's converted to a float
+    fn float(n: f32) -> String {
+        if n.is_finite() {
+            let printed = if n.fract() == 0.0 && n.abs() < 1e21 {
+                n.to_string()
+            } else {
+                let mut buffer = ryu::Buffer::new();
+                let printed = buffer.format_finite(n);
+                printed.strip_suffix(".0").unwrap_or(printed).to_string()
+            };
+            // the text is read as a double first, which is rounded twice
+            if printed
+                .parse::<f64>()
+                .is_ok_and(|m| (m as f32).to_bits() == n.to_bits())
+            {
+                return printed;
+            }
+        }
+        Self::number(n.into())
+    }
+
+    // integers used with bit32 functions are written in hexadecimal if they look like bit masks
+    fn operand_doc<'b>(&self, rvalue: &'b RValue, bit_masks: bool) -> Doc<'b> {
+        match *rvalue {
+            RValue::Literal(Literal::Number(n))
+                if bit_masks && self.options.hex_integers && Self::is_bit_mask(n) =>
+            {
+                format!("0x{:X}", n as u32).into()
+            }
+            _ => self.rvalue_doc(rvalue),
+        }
+    }
+
+    fn is_bit32_function(value: &RValue) -> bool {
+        matches!(
+            value,
+            RValue::Index(Index {
+                left: box RValue::Global(global),
+                right: box RValue::Literal(Literal::String(_)),
+            }) if global.0 == b"bit32"
+        )
+    }
+
+    fn is_bit32_call(rvalue: &RValue) -> bool {
+        matches!(rvalue, RValue::Call(call) | RValue::Select(Select::Call(call))
+            if Self::is_bit32_function(&call.value))
+    }
+
+    // integers like 0xFF00, 0x80 or 0x7FFF, small integers are more likely to be shift amounts
+    fn is_bit_mask(n: f64) -> bool {
+        if n.fract() != 0.0 || !(63.0..=u32::MAX as f64).contains(&n) {
+            return false;
+        }
+        let n = n as u32;
+        n.is_power_of_two()
+            || n.checked_add(1).is_some_and(u32::is_power_of_two)
+            || (0..8).all(|i| matches!((n >> (i * 4)) & 0xF, 0 | 0xF))
+    }
+
+    // uses the preferred quote, unless the string contains more of it than of the other one
+    pub(crate) fn quote_string(&self, string: &[u8]) -> String {
+        let count = |quote: QuoteStyle| string.iter().filter(|&&c| c == quote.quote()).count();
+        let mut quote_style = self.options.quote_style;
+        if count(quote_style) > count(quote_style.other()) {
+            quote_style = quote_style.other();
+        }
+        let quote = quote_style.quote() as char;
+        format!(
+            "{}{}{}",
+            quote,
+            Self::escape_string(string, quote_style.quote(), self.unicode_escapes()),
+            quote
+        )
+    }
+
+    fn unicode_escapes(&self) -> bool {
+        self.options.unicode_escapes && self.dialect.supports_unicode_escape()
+    }
+
+    // a long string can't be used as a key, `t[[[a]]]` is read as `t [[[a]] ]`
+    fn key_doc<'b>(&self, key: &'b RValue) -> Doc<'b> {
+        match key {
+            RValue::Literal(Literal::String(string)) => self.quote_string(string).into(),
+            _ => self.rvalue_doc(key),
+        }
+    }
+
+    fn comma_separated_docs<'b>(&self, list: &'b [RValue], bit_masks: bool) -> Vec<Doc<'b>> {
+        let mut docs = Vec::with_capacity(list.len() * 3);
+        for (index, rvalue) in list.iter().enumerate() {
+            if index != 0 {
+                docs.push(",".into());
+                docs.push(Doc::Line);
+            }
+            docs.push(self.operand_doc(rvalue, bit_masks));
+        }
+        docs
+    }
+
+    // a single value isn't nested, so only the value itself is broken
+    fn rvalue_list_doc<'b>(&self, list: &'b [RValue]) -> Doc<'b> {
+        match list {
+            [rvalue] => self.rvalue_doc(rvalue),
+            _ => Doc::group(Doc::nest(self.comma_separated_docs(list, false).into())),
+        }
+    }
+
+    fn arguments_doc<'b>(&self, arguments: &'b [RValue], bit_masks: bool) -> Doc<'b> {
+        let Some((last, rest)) = arguments.split_last() else {
+            return "()".into();
+        };
+        let mut docs = vec![Doc::SoftLine];
+        docs.extend(self.comma_separated_docs(rest, bit_masks));
+        if !rest.is_empty() {
+            docs.push(",".into());
+            docs.push(Doc::Line);
+        }
+        let wrap = matches!(last, RValue::Select(_));
+        docs.push(Doc::parenthesize(wrap, self.operand_doc(last, bit_masks)));
+        Doc::group(
+            vec![
+                "(".into(),
+                Doc::nest(docs.into()),
+                Doc::SoftLine,
+                ")".into(),
+            ]
+            .into(),
+        )
+    }
+
     pub(crate) fn is_valid_name(name: &[u8]) -> bool {
         if !(name
             .iter()
@@ -431,115 +740,165 @@ impl<'a, W: fmt::Write> Formatter<'a, W> {
         return true;
     }
 
-    // TODO: PERF: Cow like from_utf8_lossy
-    pub(crate) fn escape_string(string: &[u8]) -> Cow<str> {
-        let mut owned: Option<String> = None;
-        let mut iter = string.iter().enumerate().peekable();
-        while let Some((i, &c)) = iter.next() {
-            if c == b' ' || (c.is_ascii_graphic() && c != b'\\' && c != b'\'' && c != b'\"') {
-                if let Some(owned) = &mut owned {
-                    owned.push(c as char);
+    // only `quote` is escaped, it's the one the string is delimited with.
+    // valid utf-8 is kept as is, unless it's not printable
+    pub(crate) fn escape_string(string: &[u8], quote: u8, unicode_escapes: bool) -> Cow<str> {
+        if let Ok(string) = std::str::from_utf8(string)
+            && !string.chars().any(|c| Self::needs_escape(c, quote))
+        {
+            return string.into();
+        }
+        let mut escaped = String::with_capacity(string.len() * 2);
+        let mut offset = 0;
+        for chunk in string.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                if c == '\\' || c == quote as char {
+                    escaped.push('\\');
+                    escaped.push(c);
+                } else if !Self::needs_escape(c, quote) {
+                    escaped.push(c);
+                } else if unicode_escapes && !c.is_ascii() {
+                    write!(escaped, r"\u{{{:x}}}", c as u32).unwrap();
+                } else {
+                    for (i, byte) in c.encode_utf8(&mut [0; 4]).bytes().enumerate() {
+                        Self::escape_byte(&mut escaped, byte, string.get(offset + i + 1));
+                    }
                 }
-            } else {
-                if owned.is_none() {
-                    // TODO: PERF: unchecked?
-                    owned = Some(std::str::from_utf8(&string[..i]).unwrap().to_string());
-                    // TODO: do we want to be multiplying by 2 here?
-                    // TODO: PERF: String::with_capacity + push_str to avoid an allocation
-                    owned.as_mut().unwrap().reserve((string.len() - i) * 2);
+                offset += c.len_utf8();
+            }
+            for &byte in chunk.invalid() {
+                offset += 1;
+                Self::escape_byte(&mut escaped, byte, string.get(offset));
+            }
+        }
+        escaped.into()
+    }
+
+    fn needs_escape(c: char, quote: u8) -> bool {
+        c == '\\' || c == quote as char || !Self::is_printable(c)
+    }
+
+    // control characters, line breaks and characters that are invisible
+    // or change the direction of the text around them aren't printable
+    fn is_printable(c: char) -> bool {
+        c == ' '
+            || !(c.is_control()
+                || c.is_whitespace()
+                || matches!(
+                    c,
+                    '\u{200B}'..='\u{200F}'
+                        | '\u{202A}'..='\u{202E}'
+                        | '\u{2060}'..='\u{2064}'
+                        | '\u{2066}'..='\u{2069}'
+                        | '\u{FEFF}'
+                ))
+    }
+
+    fn escape_byte(escaped: &mut String, byte: u8, next: Option<&u8>) {
+        match byte {
+            b'\n' => escaped.push_str(r"\n"),
+            b'\r' => escaped.push_str(r"\r"),
+            b'\t' => escaped.push_str(r"\t"),
+            12 => escaped.push_str(r"\f"),
+            _ => {
+                let mut buffer = itoa::Buffer::new();
+                let printed = buffer.format(byte);
+                escaped.push('\\');
+                // `\1` followed by `2` would be read as `\12`
+                if printed.len() != 3
+                    && let Some(next) = next
+                    && next.is_ascii_digit()
+                {
+                    escaped.extend(iter::repeat('0').take(3 - printed.len()));
                 }
-                let owned = owned.as_mut().unwrap();
-                match c {
-                    b'\n' => owned.push_str(r"\n"),
-                    b'\r' => owned.push_str(r"\r"),
-                    b'\t' => owned.push_str(r"\t"),
-                    b'\"' => owned.push_str(r#"\""#),
-                    b'\'' => owned.push_str(r"\'"),
-                    b'\\' => owned.push_str(r"\\"),
-                    12 => owned.push_str(r"\f"),
-                    _ => {
-                        let mut buffer = itoa::Buffer::new();
-                        let printed = buffer.format(c);
-                        owned.push('\\');
-                        if printed.len() != 3
-                            && let Some((_, next)) = iter.peek()
-                            && next.is_ascii_digit()
-                        {
-                            owned.extend(iter::repeat('0').take(3 - printed.len()));
-                        }
-                        owned.push_str(printed);
-                    }
-                };
+                escaped.push_str(printed);
             }
         }
-        if let Some(owned) = owned {
-            owned.into()
-        } else {
-            // TODO: PERF: unchecked?
-            std::str::from_utf8(string).unwrap().into()
+    }
+
+    // `[[...]]`, or `[==[...]==]` if the string contains `]]`. only used for strings that span
+    // multiple lines and don't need escaping, escapes aren't read in long strings
+    fn long_string(string: &[u8]) -> Option<String> {
+        let string = std::str::from_utf8(string).ok()?;
+        if !string.trim_end_matches('\n').contains('\n')
+            || string
+                .chars()
+                .any(|c| c != '\n' && c != '\t' && !Self::is_printable(c))
+        {
+            return None;
         }
+        let level = (0..)
+            .find(|&level| {
+                let close = format!("]{}]", "=".repeat(level));
+                // `[[` can't be nested in lua 5.1 and the string can't end in a prefix of `close`
+                (level != 0 || !string.contains("[["))
+                    && (string.to_string() + &close).find(&close) == Some(string.len())
+            })
+            .unwrap();
+        let equals = "=".repeat(level);
+        // a line break right after the opening bracket is skipped
+        let newline = if string.starts_with('\n') { "\n" } else { "" };
+        Some(format!("[{equals}[{newline}{string}]{equals}]"))
     }
 
     pub(crate) fn format_index(&mut self, index: &Index) -> fmt::Result {
-        let wrap = Self::should_wrap_left_rvalue(&index.left);
-        if wrap {
-            write!(self.output, "(")?;
-        }
-        self.format_rvalue(&index.left)?;
-        if wrap {
-            write!(self.output, ")")?;
-        }
+        let doc = self.index_doc(index);
+        self.write_doc(&doc)
+    }
 
+    fn index_doc<'b>(&self, index: &'b Index) -> Doc<'b> {
+        let left = Doc::parenthesize(
+            Self::should_wrap_left_rvalue(&index.left),
+            self.rvalue_doc(&index.left),
+        );
         match index.right.as_ref() {
-            RValue::Literal(super::Literal::String(field)) if Self::is_valid_name(field) => {
-                write!(self.output, ".{}", std::str::from_utf8(field).unwrap())
-            }
-            _ => {
-                write!(self.output, "[")?;
-                self.format_rvalue(&index.right)?;
-                write!(self.output, "]")
-            }
+            RValue::Literal(super::Literal::String(field)) if Self::is_valid_name(field) => vec![
+                left,
+                format!(".{}", std::str::from_utf8(field).unwrap()).into(),
+            ]
+            .into(),
+            _ => vec![left, "[".into(), self.key_doc(&index.right), "]".into()].into(),
         }
     }
 
     pub(crate) fn format_call(&mut self, call: &Call) -> fmt::Result {
-        let wrap = Self::should_wrap_left_rvalue(&call.value);
-        if wrap {
-            write!(self.output, "(")?;
-        }
-        self.format_rvalue(&call.value)?;
-        if wrap {
-            write!(self.output, ")")?;
-        }
+        let doc = self.call_doc(call);
+        self.write_doc(&doc)
+    }
 
-        write!(self.output, "(")?;
-        self.format_arg_list(&call.arguments)?;
-        write!(self.output, ")")
+    fn call_doc<'b>(&self, call: &'b Call) -> Doc<'b> {
+        vec![
+            Doc::parenthesize(
+                Self::should_wrap_left_rvalue(&call.value),
+                self.rvalue_doc(&call.value),
+            ),
+            self.arguments_doc(&call.arguments, Self::is_bit32_function(&call.value)),
+        ]
+        .into()
     }
 
     pub(crate) fn format_method_call(&mut self, method_call: &MethodCall) -> fmt::Result {
-        let wrap = Self::should_wrap_left_rvalue(&method_call.value);
-        if wrap {
-            write!(self.output, "(")?;
-        }
-        self.format_rvalue(&method_call.value)?;
-        if wrap {
-            write!(self.output, ")")?;
-        }
-
-        write!(self.output, ":{}", method_call.method)?;
+        let doc = self.method_call_doc(method_call);
+        self.write_doc(&doc)
+    }
 
-        write!(self.output, "(")?;
-        self.format_arg_list(&method_call.arguments)?;
-        write!(self.output, ")")
+    fn method_call_doc<'b>(&self, method_call: &'b MethodCall) -> Doc<'b> {
+        vec![
+            Doc::parenthesize(
+                Self::should_wrap_left_rvalue(&method_call.value),
+                self.rvalue_doc(&method_call.value),
+            ),
+            format!(":{}", method_call.method).into(),
+            self.arguments_doc(&method_call.arguments, false),
+        ]
+        .into()
     }
 
     pub(crate) fn format_if(&mut self, r#if: &If) -> fmt::Result {
         write!(self.output, "if ")?;
-
-        self.format_rvalue(&r#if.condition)?;
-
-        writeln!(self.output, " then")?;
+        let doc = vec![self.rvalue_doc(&r#if.condition), " then".into()].into();
+        self.write_doc(&doc)?;
+        writeln!(self.output)?;
 
         let then_block = r#if.then_block.lock();
         if !then_block.is_empty() {
@@ -550,9 +909,27 @@ impl<'a, W: fmt::Write> Formatter<'a, W> {
         let else_block = r#if.else_block.lock();
         if !else_block.is_empty() {
             self.indent()?;
-            if let Some(else_if) = else_block.iter().exactly_one().ok().and_then(|s| s.as_if()) {
+            if let Ok(statement) = else_block.iter().exactly_one()
+                && let Some(else_if) = statement.inner().as_if()
+            {
+                // the comments attached to the `if` go before the `elseif` and after the `end`
+                // that they share
+                let (leading, trailing) = match statement {
+                    Statement::Commented(commented) => {
+                        (commented.leading.as_slice(), commented.trailing.as_slice())
+                    }
+                    _ => ([].as_slice(), [].as_slice()),
+                };
+                for comment in leading {
+                    writeln!(self.output, "{}", comment)?;
+                    self.indent()?;
+                }
                 write!(self.output, "else")?;
-                return self.format_if(else_if);
+                self.format_if(else_if)?;
+                for comment in trailing {
+                    write!(self.output, " {}", comment)?;
+                }
+                return Ok(());
             }
             writeln!(self.output, "else")?;
             self.format_block(&else_block)?;
@@ -563,6 +940,83 @@ impl<'a, W: fmt::Write> Formatter<'a, W> {
         write!(self.output, "end")
     }
 
+    pub(crate) fn format_if_expression(&mut self, if_expression: &IfExpression) -> fmt::Result {
+        let doc = self.if_expression_doc(if_expression);
+        self.write_doc(&doc)
+    }
+
+    fn if_expression_doc<'b>(&self, if_expression: &'b IfExpression) -> Doc<'b> {
+        // `if a then if b then c else d else e` is valid, but hard to read
+        let parentheses = |rvalue: &'b RValue| {
+            Doc::parenthesize(
+                matches!(rvalue, RValue::IfExpression(_)),
+                self.rvalue_doc(rvalue),
+            )
+        };
+
+        let mut docs = vec![
+            "if ".into(),
+            parentheses(&if_expression.condition),
+            " then ".into(),
+            parentheses(&if_expression.then_value),
+        ];
+        let mut else_value = &if_expression.else_value;
+        while let RValue::IfExpression(else_if) = else_value.as_ref() {
+            docs.push(" elseif ".into());
+            docs.push(parentheses(&else_if.condition));
+            docs.push(" then ".into());
+            docs.push(parentheses(&else_if.then_value));
+            else_value = &else_if.else_value;
+        }
+        docs.push(" else ".into());
+        docs.push(self.rvalue_doc(else_value));
+        docs.into()
+    }
+
+    pub(crate) fn format_commented(&mut self, commented: &Commented) -> fmt::Result {
+        let doc = self.commented_doc(commented);
+        self.write_doc(&doc)
+    }
+
+    fn commented_doc<'b>(&self, commented: &'b Commented) -> Doc<'b> {
+        let mut docs = Vec::new();
+        for comment in &commented.leading {
+            docs.push(format!("{} ", comment.inline()).into());
+        }
+        docs.push(self.rvalue_doc(&commented.value));
+        for comment in &commented.trailing {
+            docs.push(format!(" {}", comment.inline()).into());
+        }
+        docs.into()
+    }
+
+    pub(crate) fn format_interpolated_string(
+        &mut self,
+        interpolated_string: &InterpolatedString,
+    ) -> fmt::Result {
+        let doc = self.interpolated_string_doc(interpolated_string);
+        self.write_doc(&doc)
+    }
+
+    fn interpolated_string_doc<'b>(&self, interpolated_string: &'b InterpolatedString) -> Doc<'b> {
+        let mut docs = vec!["`".into()];
+        for (i, string) in interpolated_string.strings.iter().enumerate() {
+            // `{` starts a value and `}` doesn't need escaping
+            let escaped =
+                Self::escape_string(string, b'`', self.unicode_escapes()).replace('{', r"\{");
+            docs.push(escaped.into());
+            if let Some(value) = interpolated_string.values.get(i) {
+                // `{{` isn't allowed
+                let wrap = matches!(value, RValue::Table(_));
+                docs.push("{".into());
+                docs.push(Doc::parenthesize(wrap, self.rvalue_doc(value)));
+                docs.push("}".into());
+            }
+        }
+        docs.push("`".into());
+        docs.into()
+    }
+
     pub(crate) fn format_assign(&mut self, assign: &Assign) -> fmt::Result {
         if assign.prefix {
             write!(self.output, "local ")?;
@@ -573,8 +1027,33 @@ impl<'a, W: fmt::Write> Formatter<'a, W> {
             && let RValue::Closure(closure) = &assign.right[0]
         {
             let left = &assign.left[0];
-            if assign.prefix || left.as_global().is_some() || {
-                if let LValue::Index(ref index) = left {
+            let debug_name = closure.function.lock().name.clone();
+            if assign.prefix {
+                // `local function name()` would give the function another debug name, but
+                // unlike `local name = function()` the function can refer to itself
+                let local = left.as_local().unwrap();
+                let recursive = closure.upvalues.iter().any(|upvalue| match upvalue {
+                    Upvalue::Copy(upvalue) | Upvalue::Ref(upvalue) => upvalue == local,
+                });
+                let name = left.to_string();
+                if recursive || debug_name.as_deref().is_none_or(|d| d == name) {
+                    return self.format_named_function(&name, closure, false);
+                }
+            }
+            // `function name()` would give the function another debug name
+            let name = match left {
+                LValue::Global(global) => Some(&global.0),
+                LValue::Index(Index {
+                    right: box RValue::Literal(Literal::String(key)),
+                    ..
+                }) => Some(key),
+                _ => None,
+            };
+            if name.is_some_and(|name| debug_name.is_none_or(|d| d.as_bytes() == name))
+                && (left.as_global().is_some() || {
+                    let LValue::Index(ref index) = left else {
+                        unreachable!()
+                    };
                     let mut index = index;
                     let mut valid = true;
                     loop {
@@ -595,34 +1074,40 @@ impl<'a, W: fmt::Write> Formatter<'a, W> {
                         break;
                     }
                     valid
-                } else {
-                    false
+                })
+            {
+                // `function t:method()` has an implicit `self` parameter
+                if let LValue::Index(index) = left
+                    && closure
+                        .function
+                        .lock()
+                        .parameters
+                        .first()
+                        .is_some_and(|p| p.0 .0.lock().0.as_deref() == Some("self"))
+                {
+                    let name = format!("{}:{}", index.left, String::from_utf8_lossy(name.unwrap()));
+                    return self.format_named_function(&name, closure, true);
                 }
-            } {
-                return self.format_named_function(left, closure);
+                return self.format_named_function(&left.to_string(), closure, false);
             }
         }
 
+        let mut docs = Vec::new();
         for (i, lvalue) in assign.left.iter().enumerate() {
             if i != 0 {
-                write!(self.output, ", ")?;
+                docs.push(", ".into());
             }
-            self.format_lvalue(lvalue)?;
+            docs.push(self.lvalue_doc(lvalue));
         }
 
         if !assign.right.is_empty() {
-            write!(self.output, " = ")?;
+            docs.push(" = ".into());
         } else {
             assert!(assign.prefix);
         }
 
-        // TODO: REFACTOR: move to format_rvalue_list function
-        for (i, rvalue) in assign.right.iter().enumerate() {
-            if i != 0 {
-                write!(self.output, ", ")?;
-            }
-            self.format_rvalue(rvalue)?;
-        }
+        docs.push(self.rvalue_list_doc(&assign.right));
+        self.write_doc(&docs.into())?;
 
         if assign.parallel {
             write!(self.output, " -- parallel")?;
@@ -631,12 +1116,43 @@ impl<'a, W: fmt::Write> Formatter<'a, W> {
         Ok(())
     }
 
+    pub(crate) fn format_compound_assign(
+        &mut self,
+        compound_assign: &CompoundAssign,
+    ) -> fmt::Result {
+        if self.dialect.supports_compound_assign() {
+            let doc = vec![
+                self.lvalue_doc(&compound_assign.left),
+                format!(" {}= ", compound_assign.operation).into(),
+                self.rvalue_doc(&compound_assign.right),
+            ]
+            .into();
+            return self.write_doc(&doc);
+        }
+        // the target of a compound assignment can be evaluated twice
+        let binary = Binary::new(
+            match &compound_assign.left {
+                LValue::Local(local) => local.clone().into(),
+                LValue::Global(global) => global.clone().into(),
+                LValue::Index(index) => index.clone().into(),
+            },
+            compound_assign.right.clone(),
+            compound_assign.operation,
+        );
+        let doc = vec![
+            self.lvalue_doc(&compound_assign.left),
+            " = ".into(),
+            self.binary_doc(&binary),
+        ]
+        .into();
+        self.write_doc(&doc)
+    }
+
     pub(crate) fn format_while(&mut self, r#while: &While) -> fmt::Result {
         write!(self.output, "while ")?;
-
-        self.format_rvalue(&r#while.condition)?;
-
-        writeln!(self.output, " do")?;
+        let doc = vec![self.rvalue_doc(&r#while.condition), " do".into()].into();
+        self.write_doc(&doc)?;
+        writeln!(self.output)?;
 
         self.format_block(&r#while.block.lock())?;
         writeln!(self.output)?;
@@ -657,19 +1173,23 @@ impl<'a, W: fmt::Write> Formatter<'a, W> {
 
     pub(crate) fn format_numeric_for(&mut self, numeric_for: &NumericFor) -> fmt::Result {
         write!(self.output, "for {} = ", numeric_for.counter)?;
-        self.format_rvalue(&numeric_for.initial)?;
-        write!(self.output, ", ")?;
-        self.format_rvalue(&numeric_for.limit)?;
+        let mut docs = vec![
+            self.rvalue_doc(&numeric_for.initial),
+            ", ".into(),
+            self.rvalue_doc(&numeric_for.limit),
+        ];
         let skip_step = if let RValue::Literal(Literal::Number(n)) = numeric_for.step {
             n == 1.0
         } else {
             false
         };
         if !skip_step {
-            write!(self.output, ", ")?;
-            self.format_rvalue(&numeric_for.step)?;
+            docs.push(", ".into());
+            docs.push(self.rvalue_doc(&numeric_for.step));
         }
-        writeln!(self.output, " do")?;
+        docs.push(" do".into());
+        self.write_doc(&docs.into())?;
+        writeln!(self.output)?;
         self.format_block(&numeric_for.block.lock())?;
         writeln!(self.output)?;
         self.indent()?;
@@ -682,24 +1202,28 @@ impl<'a, W: fmt::Write> Formatter<'a, W> {
             "for {} in ",
             generic_for.res_locals.iter().join(", ")
         )?;
-        for (i, rvalue) in generic_for
+        let right = generic_for
             .right
             .iter()
             .enumerate()
             .rev()
             .skip_while(|(i, v)| *i != 0 && matches!(v, RValue::Literal(Literal::Nil)))
             .map(|(_, x)| x)
-            .collect_vec()
+            .collect_vec();
+        let mut docs = right
             .iter()
             .rev()
             .enumerate()
-        {
-            if i != 0 {
-                write!(self.output, ", ")?;
-            }
-            self.format_rvalue(rvalue)?;
-        }
-        writeln!(self.output, " do")?;
+            .flat_map(|(i, rvalue)| {
+                [
+                    if i != 0 { ", " } else { "" }.into(),
+                    self.rvalue_doc(rvalue),
+                ]
+            })
+            .collect_vec();
+        docs.push(" do".into());
+        self.write_doc(&docs.into())?;
+        writeln!(self.output)?;
         self.format_block(&generic_for.block.lock())?;
         writeln!(self.output)?;
         self.indent()?;
@@ -708,16 +1232,12 @@ impl<'a, W: fmt::Write> Formatter<'a, W> {
 
     pub(crate) fn format_return(&mut self, r#return: &Return) -> fmt::Result {
         write!(self.output, "return")?;
-        for (i, rvalue) in r#return.values.iter().enumerate() {
-            if i == 0 {
-                write!(self.output, " ")?;
-            } else {
-                write!(self.output, ", ")?;
-            }
-            self.format_rvalue(rvalue)?;
+        if r#return.values.is_empty() {
+            return Ok(());
         }
-
-        Ok(())
+        write!(self.output, " ")?;
+        let doc = self.rvalue_list_doc(&r#return.values);
+        self.write_doc(&doc)
     }
 
     fn format_statement(&mut self, statement: &Statement) -> fmt::Result {
@@ -725,6 +1245,9 @@ impl<'a, W: fmt::Write> Formatter<'a, W> {
 
         match statement {
             Statement::Assign(assign) => self.format_assign(assign),
+            Statement::CompoundAssign(compound_assign) => {
+                self.format_compound_assign(compound_assign)
+            }
             Statement::If(r#if) => self.format_if(r#if),
             Statement::While(r#while) => self.format_while(r#while),
             Statement::Repeat(repeat) => self.format_repeat(repeat),
@@ -737,3 +1260,187 @@ impl<'a, W: fmt::Write> Formatter<'a, W> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use by_address::ByAddress;
+    use parking_lot::Mutex;
+    use triomphe::Arc;
+
+    use super::*;
+    use crate::{Function, Global, Local, RcLocal, UnaryOperation};
+
+    fn local(name: &str) -> RcLocal {
+        RcLocal::new(Local::new(Some(name.to_string())))
+    }
+
+    fn closure(name: Option<&str>, parameters: Vec<RcLocal>, upvalues: Vec<Upvalue>) -> RValue {
+        let function = Function {
+            name: name.map(str::to_string),
+            parameters,
+            ..Default::default()
+        };
+        Closure {
+            function: ByAddress(Arc::new(Mutex::new(function))),
+            upvalues,
+        }
+        .into()
+    }
+
+    fn assign(left: LValue, right: RValue, prefix: bool) -> String {
+        let mut assign = Assign::new(vec![left], vec![right]);
+        assign.prefix = prefix;
+        Block(vec![assign.into()]).to_string()
+    }
+
+    #[test]
+    fn local_function_with_matching_name() {
+        let f = local("f");
+        let right = closure(Some("f"), Vec::new(), Vec::new());
+        assert_eq!(assign(f.into(), right, true), "local function f() end");
+    }
+
+    #[test]
+    fn local_function_with_other_name() {
+        let f = local("f");
+        let right = closure(Some("g"), Vec::new(), Vec::new());
+        assert_eq!(assign(f.into(), right, true), "local f = function() end");
+        // a function that refers to itself has to be declared with `local function`
+        let f = local("f");
+        let right = closure(Some("g"), Vec::new(), vec![Upvalue::Ref(f.clone())]);
+        assert_eq!(assign(f.into(), right, true), "local function f() end");
+    }
+
+    #[test]
+    fn anonymous_function() {
+        let right = closure(None, Vec::new(), Vec::new());
+        assert_eq!(
+            assign(local("f").into(), right, true),
+            "local function f() end"
+        );
+        let right = closure(None, Vec::new(), Vec::new());
+        let left = Index::new(
+            Global::new(b"t".to_vec()).into(),
+            Literal::String(b"f".to_vec()).into(),
+        );
+        assert_eq!(assign(left.into(), right, false), "function t.f() end");
+    }
+
+    #[test]
+    fn field_function_with_other_name() {
+        let right = closure(Some("g"), Vec::new(), Vec::new());
+        let left = Index::new(
+            Global::new(b"t".to_vec()).into(),
+            Literal::String(b"f".to_vec()).into(),
+        );
+        assert_eq!(assign(left.into(), right, false), "t.f = function() end");
+    }
+
+    #[test]
+    fn method() {
+        let right = closure(Some("m"), vec![local("self"), local("a")], Vec::new());
+        let left = Index::new(local("t").into(), Literal::String(b"m".to_vec()).into());
+        assert_eq!(assign(left.into(), right, false), "function t:m(a) end");
+    }
+
+    #[test]
+    fn bit_masks() {
+        for n in [0xFFu64, 0x80, 0x7FFF, 0xFF00, 0xFFFF_FFFF, 0x7FFF_FFFF] {
+            assert!(Formatter::<String>::is_bit_mask(n as f64), "{:#X}", n);
+        }
+        for n in [1, 32, 100, 0x1234, 0x1_0000_0000u64] {
+            assert!(!Formatter::<String>::is_bit_mask(n as f64), "{:#X}", n);
+        }
+    }
+
+    fn number(n: f64) -> RValue {
+        Literal::Number(n).into()
+    }
+
+    fn binary(left: RValue, right: RValue, operation: BinaryOperation) -> String {
+        Binary::new(left, right, operation).to_string()
+    }
+
+    fn negate(value: RValue) -> String {
+        Unary::new(value, UnaryOperation::Negate).to_string()
+    }
+
+    #[test]
+    fn infinity() {
+        assert_eq!(number(f64::INFINITY).to_string(), "1 / 0");
+        assert_eq!(number(f64::NEG_INFINITY).to_string(), "-1 / 0");
+        assert_eq!(
+            binary(number(f64::INFINITY), number(1.0), BinaryOperation::Add),
+            "1 / 0 + 1"
+        );
+        assert_eq!(
+            binary(number(f64::INFINITY), number(2.0), BinaryOperation::Pow),
+            "(1 / 0) ^ 2"
+        );
+        assert_eq!(negate(number(f64::INFINITY)), "-(1 / 0)");
+    }
+
+    #[test]
+    fn nan() {
+        assert_eq!(number(f64::NAN).to_string(), "-(0 / 0)");
+        assert_eq!(number(-f64::NAN).to_string(), "0 / 0");
+        assert_eq!(
+            binary(number(f64::NAN), number(1.0), BinaryOperation::Add),
+            "-(0 / 0) + 1"
+        );
+        assert_eq!(
+            binary(number(-f64::NAN), number(2.0), BinaryOperation::Pow),
+            "(0 / 0) ^ 2"
+        );
+        // `--` would start a comment
+        assert_eq!(negate(number(f64::NAN)), "-(-(0 / 0))");
+        assert_eq!(negate(number(-f64::NAN)), "-(0 / 0)");
+    }
+
+    #[test]
+    fn negative_zero() {
+        assert_eq!(number(-0.0).to_string(), "-0");
+        assert_eq!(
+            binary(number(1.0), number(-0.0), BinaryOperation::Add),
+            "1 + -0"
+        );
+        assert_eq!(
+            binary(number(-0.0), number(2.0), BinaryOperation::Pow),
+            "(-0) ^ 2"
+        );
+        assert_eq!(negate(number(-0.0)), "-(-0)");
+    }
+
+    fn hex_integers(value: RValue, hex_integers: bool) -> String {
+        let block = Block(vec![Return::new(vec![value]).into()]);
+        let options = FormatOptions {
+            hex_integers,
+            ..Default::default()
+        };
+        let mut output = String::new();
+        Formatter::format(&block, &mut output, Dialect::Luau, options).unwrap();
+        output
+    }
+
+    #[test]
+    fn hex_bit_masks() {
+        let band = Index::new(
+            Global::new(b"bit32".to_vec()).into(),
+            Literal::String(b"band".to_vec()).into(),
+        );
+        let call = Call::new(band.into(), vec![local("a").into(), number(65280.0)]);
+        let comparison: RValue =
+            Binary::new(call.into(), number(32768.0), BinaryOperation::Equal).into();
+        assert_eq!(
+            hex_integers(comparison.clone(), true),
+            "return bit32.band(a, 0xFF00) == 0x8000"
+        );
+        assert_eq!(
+            hex_integers(comparison, false),
+            "return bit32.band(a, 65280) == 32768"
+        );
+        // only next to bit32 calls
+        let sum = Binary::new(local("a").into(), number(65280.0), BinaryOperation::Add);
+        assert_eq!(hex_integers(sum.into(), true), "return a + 65280");
+    }
+}
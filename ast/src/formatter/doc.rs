@@ -0,0 +1,198 @@
+use std::{
+    borrow::Cow,
+    fmt::{self, Write},
+};
+
+use crate::Closure;
+
+use super::Formatter;
+
+// the width of a tab when measuring lines
+const TAB_WIDTH: usize = 4;
+
+fn text_width(text: &str) -> usize {
+    text.chars()
+        .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+        .sum()
+}
+
+// a document as described in wadler's "a prettier printer".
+// a group is written on a single line if it fits in the line width, otherwise the lines in it
+// are broken and the documents nested in it are indented.
+pub(crate) enum Doc<'a> {
+    Text(Cow<'a, str>),
+    // a space, or a line break if the group it's in is broken
+    Line,
+    // nothing, or a line break if the group it's in is broken
+    SoftLine,
+    // indents the lines in the document by one level if the group it's in is broken
+    Nest(Box<Doc<'a>>),
+    // the flag forces the group to be broken
+    Group(Box<Doc<'a>>, bool),
+    // only written if the group it's in is broken
+    IfBroken(Box<Doc<'a>>),
+    Concat(Vec<Doc<'a>>),
+    // the body of a closure is always on lines of its own, so it's written by the formatter
+    Closure(&'a Closure),
+}
+
+impl<'a> Doc<'a> {
+    pub(crate) fn nest(doc: Doc<'a>) -> Self {
+        Self::Nest(Box::new(doc))
+    }
+
+    pub(crate) fn group(doc: Doc<'a>) -> Self {
+        Self::Group(Box::new(doc), false)
+    }
+
+    pub(crate) fn broken_group(doc: Doc<'a>) -> Self {
+        Self::Group(Box::new(doc), true)
+    }
+
+    pub(crate) fn if_broken(doc: Doc<'a>) -> Self {
+        Self::IfBroken(Box::new(doc))
+    }
+
+    pub(crate) fn parenthesize(wrap: bool, doc: Doc<'a>) -> Self {
+        if wrap {
+            Self::Concat(vec!["(".into(), doc, ")".into()])
+        } else {
+            doc
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Doc<'a> {
+    fn from(text: &'a str) -> Self {
+        Self::Text(text.into())
+    }
+}
+
+impl From<String> for Doc<'_> {
+    fn from(text: String) -> Self {
+        Self::Text(text.into())
+    }
+}
+
+impl<'a> From<Vec<Doc<'a>>> for Doc<'a> {
+    fn from(docs: Vec<Doc<'a>>) -> Self {
+        Self::Concat(docs)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+// keeps track of the column that is written to next
+pub(crate) struct Output<'a, W: fmt::Write> {
+    inner: &'a mut W,
+    column: usize,
+}
+
+impl<'a, W: fmt::Write> Output<'a, W> {
+    pub(crate) fn new(inner: &'a mut W) -> Self {
+        Self { inner, column: 0 }
+    }
+}
+
+impl<W: fmt::Write> fmt::Write for Output<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match s.rsplit_once('\n') {
+            Some((_, line)) => self.column = text_width(line),
+            None => self.column += text_width(s),
+        }
+        self.inner.write_str(s)
+    }
+}
+
+impl<W: fmt::Write> Formatter<'_, W> {
+    pub(crate) fn write_doc(&mut self, doc: &Doc) -> fmt::Result {
+        let mut stack = vec![(self.indentation_level, Mode::Break, doc)];
+        while let Some((indentation_level, mode, doc)) = stack.pop() {
+            match doc {
+                Doc::Text(text) => self.output.write_str(text)?,
+                Doc::Line | Doc::SoftLine if mode == Mode::Break => {
+                    writeln!(self.output)?;
+                    self.options
+                        .indentation_mode
+                        .display(&mut self.output, indentation_level)?;
+                }
+                Doc::Line => self.output.write_char(' ')?,
+                Doc::SoftLine => {}
+                Doc::Nest(doc) => {
+                    let indentation_level = match mode {
+                        Mode::Flat => indentation_level,
+                        Mode::Break => indentation_level + 1,
+                    };
+                    stack.push((indentation_level, mode, doc));
+                }
+                // a group inside a flat group is checked as well, the flat group may have
+                // been measured up to the body of a closure
+                Doc::Group(doc, broken) => {
+                    let mode = if *broken || !self.fits(doc, &stack) {
+                        Mode::Break
+                    } else {
+                        Mode::Flat
+                    };
+                    stack.push((indentation_level, mode, doc));
+                }
+                Doc::IfBroken(doc) if mode == Mode::Break => {
+                    stack.push((indentation_level, mode, doc))
+                }
+                Doc::IfBroken(_) => {}
+                Doc::Concat(docs) => {
+                    stack.extend(docs.iter().rev().map(|doc| (indentation_level, mode, doc)))
+                }
+                Doc::Closure(closure) => {
+                    let previous_level =
+                        std::mem::replace(&mut self.indentation_level, indentation_level);
+                    self.format_closure(closure)?;
+                    self.indentation_level = previous_level;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // whether `doc` fits on the rest of the line when it's flat,
+    // including what follows it up to the next line break
+    fn fits(&self, doc: &Doc, rest: &[(usize, Mode, &Doc)]) -> bool {
+        let mut width = self.options.line_width as isize - self.output.column as isize;
+        let mut stack = vec![(Mode::Flat, doc)];
+        let mut rest = rest.iter().rev().map(|&(_, mode, doc)| (mode, doc));
+        while width >= 0 {
+            let Some((mode, doc)) = stack.pop().or_else(|| rest.next()) else {
+                return true;
+            };
+            match doc {
+                Doc::Text(text) => {
+                    if let Some((line, _)) = text.split_once('\n') {
+                        return width >= text_width(line) as isize;
+                    }
+                    width -= text_width(text) as isize;
+                }
+                Doc::Line | Doc::SoftLine if mode == Mode::Break => return true,
+                Doc::Line => width -= 1,
+                Doc::SoftLine => {}
+                Doc::Nest(doc) => stack.push((mode, doc)),
+                Doc::Group(doc, broken) => {
+                    stack.push((if *broken { Mode::Break } else { mode }, doc))
+                }
+                Doc::IfBroken(doc) if mode == Mode::Break => stack.push((mode, doc)),
+                Doc::IfBroken(_) => {}
+                Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (mode, doc))),
+                Doc::Closure(closure) => {
+                    width -= text_width(&Self::closure_header(closure)) as isize;
+                    if !closure.function.lock().body.is_empty() {
+                        return width >= 0;
+                    }
+                    width -= " end".len() as isize;
+                }
+            }
+        }
+        false
+    }
+}
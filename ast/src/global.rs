@@ -36,7 +36,7 @@ impl fmt::Display for Global {
             write!(
                 f,
                 "__FENV[\"{}\"]",
-                Formatter::<fmt::Formatter>::escape_string(&self.0)
+                Formatter::<fmt::Formatter>::escape_string(&self.0, b'"', false)
             )
         }
     }
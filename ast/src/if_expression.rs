@@ -0,0 +1,128 @@
+use std::fmt;
+
+use crate::{formatter::Formatter, LocalRw, RValue, RcLocal, SideEffects, Traverse};
+
+// luau's `if a then b else c`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfExpression {
+    pub condition: Box<RValue>,
+    pub then_value: Box<RValue>,
+    pub else_value: Box<RValue>,
+}
+
+impl IfExpression {
+    pub fn new(condition: RValue, then_value: RValue, else_value: RValue) -> Self {
+        Self {
+            condition: Box::new(condition),
+            then_value: Box::new(then_value),
+            else_value: Box::new(else_value),
+        }
+    }
+
+    // the else value extends as far to the right as possible,
+    // so an if expression has to be wrapped when it is an operand
+    pub fn precedence(&self) -> usize {
+        0
+    }
+}
+
+impl SideEffects for IfExpression {
+    fn has_side_effects(&self) -> bool {
+        self.condition.has_side_effects()
+            || self.then_value.has_side_effects()
+            || self.else_value.has_side_effects()
+    }
+}
+
+impl Traverse for IfExpression {
+    fn rvalues_mut(&mut self) -> Vec<&mut RValue> {
+        vec![
+            &mut self.condition,
+            &mut self.then_value,
+            &mut self.else_value,
+        ]
+    }
+
+    fn rvalues(&self) -> Vec<&RValue> {
+        vec![&self.condition, &self.then_value, &self.else_value]
+    }
+}
+
+impl LocalRw for IfExpression {
+    fn values_read(&self) -> Vec<&RcLocal> {
+        self.condition
+            .values_read()
+            .into_iter()
+            .chain(self.then_value.values_read())
+            .chain(self.else_value.values_read())
+            .collect()
+    }
+
+    fn values_read_mut(&mut self) -> Vec<&mut RcLocal> {
+        self.condition
+            .values_read_mut()
+            .into_iter()
+            .chain(self.then_value.values_read_mut())
+            .chain(self.else_value.values_read_mut())
+            .collect()
+    }
+}
+
+impl fmt::Display for IfExpression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Formatter::new(f).format_if_expression(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Binary, BinaryOperation, Global, Literal, Unary, UnaryOperation};
+
+    fn global(name: &str) -> RValue {
+        Global::new(name.as_bytes().to_vec()).into()
+    }
+
+    fn if_expression(condition: &str, then_value: &str, else_value: RValue) -> RValue {
+        IfExpression::new(global(condition), global(then_value), else_value).into()
+    }
+
+    #[test]
+    fn left_operand_parenthesized() {
+        let left = if_expression("c", "a", global("b"));
+        let binary = Binary::new(left, Literal::Number(1.0).into(), BinaryOperation::Add);
+        assert_eq!(binary.to_string(), "(if c then a else b) + 1");
+    }
+
+    #[test]
+    fn right_operand_parenthesized() {
+        let right = if_expression("c", "a", global("b"));
+        let binary = Binary::new(global("d"), right, BinaryOperation::Or);
+        assert_eq!(binary.to_string(), "d or (if c then a else b)");
+    }
+
+    #[test]
+    fn unary_operand_parenthesized() {
+        let unary = Unary::new(if_expression("c", "a", global("b")), UnaryOperation::Not);
+        assert_eq!(unary.to_string(), "not (if c then a else b)");
+    }
+
+    #[test]
+    fn nested_condition_parenthesized() {
+        let condition = if_expression("c", "a", global("b"));
+        let if_expression = IfExpression::new(condition, global("d"), global("e"));
+        assert_eq!(
+            if_expression.to_string(),
+            "if (if c then a else b) then d else e"
+        );
+    }
+
+    #[test]
+    fn else_if_chain() {
+        let else_value = if_expression("d", "b", global("e"));
+        assert_eq!(
+            if_expression("c", "a", else_value).to_string(),
+            "if c then a elseif d then b else e"
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use std::fmt;
+
+use crate::{
+    formatter::Formatter, Block, Literal, LocalRw, MethodCall, RValue, RcLocal, Select,
+    SideEffects, Statement, Traverse,
+};
+
+// luau's `` `text {value} more text` ``
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterpolatedString {
+    // the text around the values, there is always one more string than there are values
+    pub strings: Vec<Vec<u8>>,
+    pub values: Vec<RValue>,
+}
+
+impl InterpolatedString {
+    pub fn new(strings: Vec<Vec<u8>>, values: Vec<RValue>) -> Self {
+        assert_eq!(strings.len(), values.len() + 1);
+        Self { strings, values }
+    }
+
+    // luau compiles interpolated strings to `("text %* more text"):format(value)`
+    fn from_format(method_call: &MethodCall) -> Option<Self> {
+        let RValue::Literal(Literal::String(format)) = method_call.value.as_ref() else {
+            return None;
+        };
+        if method_call.method != "format"
+            || method_call
+                .arguments
+                .iter()
+                .any(|a| matches!(a, RValue::Select(_) | RValue::VarArg(_)))
+        {
+            return None;
+        }
+
+        let mut strings = vec![Vec::new()];
+        let mut iter = format.iter();
+        while let Some(&c) = iter.next() {
+            if c == b'%' {
+                match iter.next() {
+                    Some(b'%') => strings.last_mut().unwrap().push(b'%'),
+                    Some(b'*') => strings.push(Vec::new()),
+                    _ => return None,
+                }
+            } else {
+                strings.last_mut().unwrap().push(c);
+            }
+        }
+        if strings.len() == 1 || strings.len() != method_call.arguments.len() + 1 {
+            return None;
+        }
+        Some(Self::new(strings, method_call.arguments.clone()))
+    }
+}
+
+impl SideEffects for InterpolatedString {
+    fn has_side_effects(&self) -> bool {
+        self.values.iter().any(|value| value.has_side_effects())
+    }
+}
+
+impl Traverse for InterpolatedString {
+    fn rvalues_mut(&mut self) -> Vec<&mut RValue> {
+        self.values.iter_mut().collect()
+    }
+
+    fn rvalues(&self) -> Vec<&RValue> {
+        self.values.iter().collect()
+    }
+}
+
+impl LocalRw for InterpolatedString {
+    fn values_read(&self) -> Vec<&RcLocal> {
+        self.values.iter().flat_map(|v| v.values_read()).collect()
+    }
+
+    fn values_read_mut(&mut self) -> Vec<&mut RcLocal> {
+        self.values
+            .iter_mut()
+            .flat_map(|v| v.values_read_mut())
+            .collect()
+    }
+}
+
+impl fmt::Display for InterpolatedString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Formatter::new(f).format_interpolated_string(self)
+    }
+}
+
+/// Replaces `("text %* more text"):format(value)` with `` `text {value} more text` ``
+/// in `block` and the blocks nested in it.
+pub fn interpolate_strings(block: &mut Block) {
+    for statement in block.iter_mut() {
+        statement.post_traverse_rvalues(&mut |rvalue| {
+            if let RValue::MethodCall(method_call)
+            | RValue::Select(Select::MethodCall(method_call)) = rvalue
+                && let Some(interpolated_string) = InterpolatedString::from_format(method_call)
+            {
+                *rvalue = interpolated_string.into();
+            }
+            None::<()>
+        });
+        match statement.inner_mut() {
+            Statement::If(r#if) => {
+                interpolate_strings(&mut r#if.then_block.lock());
+                interpolate_strings(&mut r#if.else_block.lock());
+            }
+            Statement::While(r#while) => interpolate_strings(&mut r#while.block.lock()),
+            Statement::Repeat(repeat) => interpolate_strings(&mut repeat.block.lock()),
+            Statement::NumericFor(numeric_for) => {
+                interpolate_strings(&mut numeric_for.block.lock())
+            }
+            Statement::GenericFor(generic_for) => {
+                interpolate_strings(&mut generic_for.block.lock())
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Call, Global, If, Local, Table, VarArg};
+
+    fn global(name: &str) -> RValue {
+        Global::new(name.as_bytes().to_vec()).into()
+    }
+
+    fn format(format: &str, arguments: Vec<RValue>) -> MethodCall {
+        MethodCall::new(
+            Literal::String(format.as_bytes().to_vec()).into(),
+            "format".to_string(),
+            arguments,
+        )
+    }
+
+    fn interpolate(format: &str, arguments: Vec<RValue>) -> Option<String> {
+        InterpolatedString::from_format(&self::format(format, arguments))
+            .map(|interpolated_string| interpolated_string.to_string())
+    }
+
+    #[test]
+    fn values_interpolated() {
+        assert_eq!(
+            interpolate("a %* b %*", vec![global("x"), global("y")]).as_deref(),
+            Some("`a {x} b {y}`")
+        );
+    }
+
+    #[test]
+    fn percent_unescaped() {
+        assert_eq!(
+            interpolate("%*%% done", vec![global("x")]).as_deref(),
+            Some("`{x}% done`")
+        );
+    }
+
+    #[test]
+    fn opening_brace_escaped() {
+        assert_eq!(
+            interpolate("{%*}", vec![global("x")]).as_deref(),
+            Some(r"`\{{x}}`")
+        );
+    }
+
+    #[test]
+    fn table_value_parenthesized() {
+        assert_eq!(
+            interpolate("%*", vec![Table::default().into()]).as_deref(),
+            Some("`{({})}`")
+        );
+    }
+
+    #[test]
+    fn other_specifiers_rejected() {
+        assert_eq!(interpolate("%d", vec![global("x")]), None);
+        assert_eq!(interpolate("%* %s", vec![global("x"), global("y")]), None);
+    }
+
+    #[test]
+    fn no_values_rejected() {
+        assert_eq!(interpolate("100%%", Vec::new()), None);
+    }
+
+    #[test]
+    fn argument_count_mismatch_rejected() {
+        assert_eq!(interpolate("%* %*", vec![global("x")]), None);
+        assert_eq!(interpolate("%*", vec![global("x"), global("y")]), None);
+    }
+
+    #[test]
+    fn multiple_results_rejected() {
+        // `...` and calls can expand to any number of values
+        assert_eq!(interpolate("%*", vec![VarArg.into()]), None);
+        let call = Call::new(global("f"), Vec::new());
+        assert_eq!(interpolate("%*", vec![Select::Call(call).into()]), None);
+    }
+
+    #[test]
+    fn side_effects_depend_on_values() {
+        let interpolated_string = |value| InterpolatedString::new(vec![Vec::new(); 2], vec![value]);
+        let local = RcLocal::new(Local::new(Some("x".to_string())));
+        assert!(!interpolated_string(local.into()).has_side_effects());
+        let call = Call::new(global("f"), Vec::new()).into();
+        assert!(interpolated_string(call).has_side_effects());
+    }
+
+    #[test]
+    fn nested_blocks_interpolated() {
+        let call = Call::new(
+            global("print"),
+            vec![format("%*!", vec![global("x")]).into()],
+        );
+        let r#if = If::new(global("c"), Block(vec![call.into()]), Block::default());
+        let mut block = Block(vec![r#if.into()]);
+        interpolate_strings(&mut block);
+        assert_eq!(block.to_string(), "if c then\n\tprint(`{x}!`)\nend");
+    }
+}
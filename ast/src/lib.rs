@@ -18,18 +18,25 @@ mod r#break;
 mod call;
 mod close;
 mod closure;
+mod comment;
+mod compound_assign;
 mod r#continue;
+mod dialect;
 mod r#for;
 pub mod formatter;
 mod global;
 mod goto;
 mod r#if;
+mod if_expression;
 mod index;
+mod interpolated_string;
 mod literal;
 mod local;
 //mod name_gen;
 pub mod local_declarations;
+pub mod methods;
 pub mod name_locals;
+pub mod rename_local;
 mod repeat;
 pub mod replace_locals;
 mod r#return;
@@ -47,9 +54,14 @@ pub use binary::*;
 pub use call::*;
 pub use close::*;
 pub use closure::*;
+pub use comment::*;
+pub use compound_assign::*;
+pub use dialect::*;
 pub use global::*;
 pub use goto::*;
+pub use if_expression::*;
 pub use index::*;
+pub use interpolated_string::*;
 pub use literal::*;
 pub use local::*;
 pub use r#break::*;
@@ -105,6 +117,9 @@ pub enum RValue {
     Binary(Binary),
     Closure(Closure),
     Select(Select),
+    IfExpression(IfExpression),
+    InterpolatedString(InterpolatedString),
+    Commented(Commented),
 }
 
 impl type_system::Infer for RValue {
@@ -119,6 +134,9 @@ impl type_system::Infer for RValue {
             RValue::Unary(_) => Type::Any,
             RValue::Binary(_) => Type::Any,
             RValue::Closure(closure) => closure.infer(system),
+            RValue::IfExpression(_) => Type::Any,
+            RValue::InterpolatedString(_) => Type::String,
+            RValue::Commented(commented) => commented.value.infer(system),
             _ => Type::VarArg,
         }
     }
@@ -132,6 +150,7 @@ impl<'a: 'b, 'b> Reduce for RValue {
             Self::Literal(literal) => literal.reduce(),
             Self::Table(table) => table.reduce(),
             Self::Closure(closure) => closure.reduce(),
+            Self::Commented(commented) => commented.reduce(),
             other => other,
         }
     }
@@ -143,6 +162,7 @@ impl<'a: 'b, 'b> Reduce for RValue {
             Self::Literal(literal) => literal.reduce_condition(),
             Self::Table(table) => table.reduce_condition(),
             Self::Closure(closure) => closure.reduce_condition(),
+            Self::Commented(commented) => commented.reduce_condition(),
             other => other,
         }
     }
@@ -153,9 +173,16 @@ impl RValue {
         match self {
             Self::Binary(binary) => binary.precedence(),
             Self::Unary(unary) => unary.precedence(),
-            RValue::Literal(Literal::Number(n)) if n.is_finite() && n.is_sign_negative() => {
-                return 7;
+            Self::IfExpression(if_expression) => if_expression.precedence(),
+            Self::Commented(commented) => commented.precedence(),
+            // `1 / 0`, `-1 / 0` and `0 / 0`
+            RValue::Literal(Literal::Number(n))
+                if n.is_infinite() || (n.is_nan() && n.is_sign_negative()) =>
+            {
+                6
             }
+            // `-1`, `-0` and `-(0 / 0)`
+            RValue::Literal(Literal::Number(n)) if n.is_sign_negative() || n.is_nan() => 7,
             _ => 9,
         }
     }
@@ -185,6 +212,11 @@ impl fmt::Display for RValue {
             RValue::Binary(binary) => write!(f, "{}", binary),
             RValue::Closure(closure) => write!(f, "{}", closure),
             RValue::Select(select) => write!(f, "{}", select),
+            RValue::IfExpression(if_expression) => write!(f, "{}", if_expression),
+            RValue::InterpolatedString(interpolated_string) => {
+                write!(f, "{}", interpolated_string)
+            }
+            RValue::Commented(commented) => write!(f, "{}", commented),
         }
     }
 }
@@ -241,23 +273,6 @@ impl fmt::Display for LValue {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub struct Comment {
-    pub text: String,
-}
-
-impl Comment {
-    pub fn new(text: String) -> Self {
-        Self { text }
-    }
-}
-
-impl Traverse for Comment {}
-
-impl SideEffects for Comment {}
-
-impl LocalRw for Comment {}
-
 #[enum_dispatch(LocalRw, SideEffects, Traverse)]
 #[derive(Debug, Clone, PartialEq, EnumAsInner)]
 pub enum Statement {
@@ -265,6 +280,7 @@ pub enum Statement {
     Call(Call),
     MethodCall(MethodCall),
     Assign(Assign),
+    CompoundAssign(CompoundAssign),
     If(If),
     Goto(Goto),
     Label(Label),
@@ -282,6 +298,7 @@ pub enum Statement {
     Close(Close),
     SetList(SetList),
     Comment(Comment),
+    Commented(CommentedStatement),
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -299,12 +316,6 @@ impl fmt::Display for Empty {
     }
 }
 
-impl fmt::Display for Comment {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "-- {}", self.text)
-    }
-}
-
 impl fmt::Display for Statement {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -312,6 +323,7 @@ impl fmt::Display for Statement {
             Statement::Call(call) => write!(f, "{}", call),
             Statement::MethodCall(method_call) => write!(f, "{}", method_call),
             Statement::Assign(assign) => write!(f, "{}", assign),
+            Statement::CompoundAssign(compound_assign) => write!(f, "{}", compound_assign),
             // TODO: STYLE: replace all `if_` with `r#if`, etc
             Statement::If(if_) => write!(f, "{}", if_),
             Statement::Goto(goto) => write!(f, "{}", goto),
@@ -328,6 +340,7 @@ impl fmt::Display for Statement {
             Statement::Continue(continue_) => write!(f, "{}", continue_),
             Statement::Break(break_) => write!(f, "{}", break_),
             Statement::Comment(comment) => write!(f, "{}", comment),
+            Statement::Commented(commented) => write!(f, "{}", commented),
             Statement::SetList(setlist) => write!(f, "{}", setlist),
             Statement::Close(close) => write!(f, "{}", close),
             Statement::Empty(empty) => write!(f, "{}", empty),
@@ -355,6 +368,6 @@ impl DerefMut for Block {
 
 impl fmt::Display for Block {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        Formatter::format(self, f, Default::default())
+        Formatter::format(self, f, Default::default(), Default::default())
     }
 }
@@ -13,7 +13,8 @@ pub enum Literal {
     Boolean(bool),
     Number(f64),
     String(Vec<u8>),
-    Vector(f32, f32, f32),
+    // the w component is 0 unless luau was built with 4-wide vectors
+    Vector(f32, f32, f32, f32),
 }
 
 impl Reduce for Literal {
@@ -59,26 +60,6 @@ impl Traverse for Literal {}
 
 impl fmt::Display for Literal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Literal::Nil => write!(f, "nil"),
-            Literal::Boolean(value) => write!(f, "{}", value),
-            &Literal::Number(value) => {
-                // TODO: this is a bit messy, just use `buffer.format` here and format_finite
-                // in formatter.rs
-                debug_assert!(value.is_finite());
-                // TODO: fork ryu to remove ".0"
-                let mut buffer = ryu::Buffer::new();
-                let printed = buffer.format_finite(value);
-                write!(f, "{}", printed.strip_suffix(".0").unwrap_or(printed))
-            }
-            Literal::String(value) => {
-                write!(
-                    f,
-                    "\"{}\"",
-                    Formatter::<fmt::Formatter>::escape_string(value)
-                )
-            }
-            Literal::Vector(x, y, z) => write!(f, "Vector3.new({}, {}, {})", x, y, z),
-        }
+        Formatter::new(f).format_literal(self)
     }
 }
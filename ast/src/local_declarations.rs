@@ -15,6 +15,41 @@ use triomphe::Arc;
 
 use crate::{Assign, Block, LocalRw, RcLocal, Statement};
 
+// removes locals that are declared elsewhere (i.e. parameters and upvalues) from declarations
+// that were inserted before local declaration, such as the ones hoisted out of goto dispatch loops
+fn remove_ignored_declarations(block: &mut Block, locals_to_ignore: &FxHashSet<RcLocal>) {
+    block.retain_mut(|stat| match stat.inner_mut() {
+        Statement::Assign(assign) if assign.prefix && assign.right.is_empty() => {
+            assign
+                .left
+                .retain(|l| !l.as_local().is_some_and(|l| locals_to_ignore.contains(l)));
+            !assign.left.is_empty()
+        }
+        Statement::If(r#if) => {
+            remove_ignored_declarations(&mut r#if.then_block.lock(), locals_to_ignore);
+            remove_ignored_declarations(&mut r#if.else_block.lock(), locals_to_ignore);
+            true
+        }
+        Statement::While(r#while) => {
+            remove_ignored_declarations(&mut r#while.block.lock(), locals_to_ignore);
+            true
+        }
+        Statement::Repeat(repeat) => {
+            remove_ignored_declarations(&mut repeat.block.lock(), locals_to_ignore);
+            true
+        }
+        Statement::NumericFor(numeric_for) => {
+            remove_ignored_declarations(&mut numeric_for.block.lock(), locals_to_ignore);
+            true
+        }
+        Statement::GenericFor(generic_for) => {
+            remove_ignored_declarations(&mut generic_for.block.lock(), locals_to_ignore);
+            true
+        }
+        _ => true,
+    });
+}
+
 #[derive(Default)]
 pub struct LocalDeclarer {
     block_to_node: FxHashMap<ByAddress<Arc<Mutex<Block>>>, NodeIndex>,
@@ -29,7 +64,10 @@ impl LocalDeclarer {
         self.block_to_node.insert(block.clone().into(), node);
         for (stat_index, stat) in block.lock().iter().enumerate() {
             // for loops already declare their own locals :)
-            if !matches!(stat, Statement::GenericFor(_) | Statement::NumericFor(_)) {
+            if !matches!(
+                stat.inner(),
+                Statement::GenericFor(_) | Statement::NumericFor(_)
+            ) {
                 // we only visit locals written because locals are guaranteed to be written
                 // before they are read.
                 // TODO: move to seperate function and visit breadth-first?
@@ -41,7 +79,7 @@ impl LocalDeclarer {
                         .or_insert(stat_index);
                 }
             }
-            match stat {
+            match stat.inner() {
                 Statement::If(r#if) => {
                     let if_node = self.graph.add_node((None, stat_index));
                     self.graph.add_edge(node, if_node, ());
@@ -72,14 +110,43 @@ impl LocalDeclarer {
         node
     }
 
+    // the block and index of the `if` whose `else` block is `node`, if that block is nothing but
+    // another `if`, i.e. an `elseif`
+    fn else_if_parent(&self, node: NodeIndex) -> Option<(NodeIndex, usize)> {
+        let block = self.graph.node_weight(node).unwrap().0.as_ref()?;
+        if !matches!(&block.lock()[..], [statement] if statement.inner().as_if().is_some()) {
+            return None;
+        }
+        let if_node = self
+            .graph
+            .neighbors_directed(node, Direction::Incoming)
+            .exactly_one()
+            .ok()?;
+        let &(None, stat_index) = self.graph.node_weight(if_node).unwrap() else {
+            return None;
+        };
+        let parent = self
+            .graph
+            .neighbors_directed(if_node, Direction::Incoming)
+            .exactly_one()
+            .ok()?;
+        let parent_block = self.graph.node_weight(parent).unwrap().0.as_ref()?;
+        let is_else = parent_block.lock()[stat_index]
+            .inner()
+            .as_if()
+            .is_some_and(|r#if| Arc::ptr_eq(&r#if.else_block, block));
+        is_else.then_some((parent, stat_index))
+    }
+
     pub fn declare_locals(
         mut self,
         root_block: Arc<Mutex<Block>>,
         locals_to_ignore: &FxHashSet<RcLocal>,
     ) {
+        remove_ignored_declarations(&mut root_block.lock(), locals_to_ignore);
         let root_node = self.visit(root_block, 0);
         let dominators = simple_fast(&self.graph, root_node);
-        for (local, usages) in self.local_usages {
+        for (local, usages) in std::mem::take(&mut self.local_usages) {
             if locals_to_ignore.contains(&local) {
                 continue;
             }
@@ -126,6 +193,13 @@ impl LocalDeclarer {
                     .unwrap();
                 (node, first_stat_index) = (parent, *parent_stat_index);
             }
+            // a declaration in front of an `elseif` would break up the chain,
+            // so the local is declared before the outer `if` instead
+            while first_stat_index == 0
+                && let Some((parent, parent_stat_index)) = self.else_if_parent(node)
+            {
+                (node, first_stat_index) = (parent, parent_stat_index);
+            }
             let block = self
                 .graph
                 .node_weight(node)
@@ -145,7 +219,8 @@ impl LocalDeclarer {
         for (ByAddress(block), declarations) in self.declarations {
             let mut block = block.lock();
             for (stat_index, mut locals) in declarations.into_iter().rev() {
-                match &mut block[stat_index] {
+                // the declaration goes before the comments attached to the statement
+                match block[stat_index].inner_mut() {
                     Statement::Assign(assign)
                         if assign
                             .left
@@ -173,3 +248,108 @@ impl LocalDeclarer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{comment_before, Call, Comment, Global, If, Literal, Local, RValue};
+
+    fn global(name: &str) -> RValue {
+        Global::new(name.as_bytes().to_vec()).into()
+    }
+
+    fn call(name: &str) -> Statement {
+        Call::new(global(name), Vec::new()).into()
+    }
+
+    fn set(local: &RcLocal, value: f64) -> Statement {
+        Assign::new(
+            vec![local.clone().into()],
+            vec![Literal::Number(value).into()],
+        )
+        .into()
+    }
+
+    fn r#if(condition: &str, then_block: Vec<Statement>, else_block: Vec<Statement>) -> Statement {
+        If::new(global(condition), Block(then_block), Block(else_block)).into()
+    }
+
+    fn declare(block: Vec<Statement>) -> String {
+        let block = Arc::new(Mutex::new(Block(block)));
+        LocalDeclarer::default().declare_locals(block.clone(), &FxHashSet::default());
+        let block = block.lock();
+        block.to_string()
+    }
+
+    #[test]
+    fn declared_before_else_if_chain() {
+        let x = RcLocal::new(Local::new(Some("x".to_string())));
+        let block = vec![r#if(
+            "a",
+            vec![call("f")],
+            vec![r#if(
+                "b",
+                vec![set(&x, 1.0)],
+                vec![r#if("c", vec![set(&x, 2.0)], vec![set(&x, 3.0)])],
+            )],
+        )];
+        assert_eq!(
+            declare(block),
+            "\
+local x
+if a then
+	f()
+elseif b then
+	x = 1
+elseif c then
+	x = 2
+else
+	x = 3
+end"
+        );
+    }
+
+    #[test]
+    fn declared_before_commented_else_if() {
+        let x = RcLocal::new(Local::new(Some("x".to_string())));
+        let mut else_if = r#if("b", vec![set(&x, 1.0)], vec![set(&x, 2.0)]);
+        comment_before(&mut else_if, Comment::new("d".to_string()));
+        let block = vec![r#if("a", vec![call("f")], vec![else_if])];
+        assert_eq!(
+            declare(block),
+            "\
+local x
+if a then
+	f()
+-- d
+elseif b then
+	x = 1
+else
+	x = 2
+end"
+        );
+    }
+
+    #[test]
+    fn declared_in_then_block() {
+        // only an `else` block with nothing but an `if` is an `elseif`
+        let x = RcLocal::new(Local::new(Some("x".to_string())));
+        let block = vec![r#if(
+            "a",
+            vec![r#if("b", vec![set(&x, 1.0)], vec![set(&x, 2.0)])],
+            Vec::new(),
+        )];
+        assert_eq!(
+            declare(block),
+            "\
+if a then
+	local x
+	if b then
+		x = 1
+	else
+		x = 2
+	end
+end"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use rustc_hash::FxHashSet;
+
+use crate::{Block, Index, LValue, Literal, RValue, RcLocal, Select, Statement, Traverse};
+
+// the table a method is called on or stored in
+#[derive(PartialEq, Eq, Hash)]
+enum Receiver {
+    Local(RcLocal),
+    Global(Vec<u8>),
+}
+
+impl Receiver {
+    fn new(value: &RValue) -> Option<Self> {
+        match value {
+            RValue::Local(local) => Some(Self::Local(local.clone())),
+            RValue::Global(global) => Some(Self::Global(global.0.clone())),
+            _ => None,
+        }
+    }
+}
+
+// calls `visit` with every statement in `block`, including the ones in closures
+fn visit_statements(block: &mut Block, visit: &mut impl FnMut(&mut Statement)) {
+    for statement in block.iter_mut() {
+        let statement = statement.inner_mut();
+        visit(statement);
+        statement.post_traverse_rvalues(&mut |rvalue| -> Option<()> {
+            if let RValue::Closure(closure) = rvalue {
+                visit_statements(&mut closure.function.lock().body, visit);
+            }
+            None
+        });
+        match statement {
+            Statement::If(r#if) => {
+                visit_statements(&mut r#if.then_block.lock(), visit);
+                visit_statements(&mut r#if.else_block.lock(), visit);
+            }
+            Statement::While(r#while) => visit_statements(&mut r#while.block.lock(), visit),
+            Statement::Repeat(repeat) => visit_statements(&mut repeat.block.lock(), visit),
+            Statement::NumericFor(numeric_for) => {
+                visit_statements(&mut numeric_for.block.lock(), visit)
+            }
+            Statement::GenericFor(generic_for) => {
+                visit_statements(&mut generic_for.block.lock(), visit)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Names the first parameter of the closures assigned to `t.name` `self`
+/// if `t:name()` is called anywhere in `block` on the same local or global `t`,
+/// so that they are written as `function t:name()`.
+/// Methods with debug info already have a parameter named `self`.
+pub fn recover_methods(block: &mut Block) {
+    let mut methods = FxHashSet::default();
+    visit_statements(block, &mut |statement| {
+        if let Statement::MethodCall(method_call) = statement
+            && let Some(receiver) = Receiver::new(&method_call.value)
+        {
+            methods.insert((receiver, method_call.method.clone()));
+        }
+        statement.post_traverse_rvalues(&mut |rvalue| -> Option<()> {
+            if let RValue::MethodCall(method_call)
+            | RValue::Select(Select::MethodCall(method_call)) = rvalue
+                && let Some(receiver) = Receiver::new(&method_call.value)
+            {
+                methods.insert((receiver, method_call.method.clone()));
+            }
+            None
+        });
+    });
+    visit_statements(block, &mut |statement| {
+        if let Statement::Assign(assign) = statement
+            && !assign.prefix
+            && let [LValue::Index(Index {
+                left,
+                right: box RValue::Literal(Literal::String(key)),
+            })] = assign.left.as_slice()
+            && let [RValue::Closure(closure)] = assign.right.as_slice()
+            && let Some(receiver) = Receiver::new(left)
+            && methods.contains(&(receiver, String::from_utf8_lossy(key).into_owned()))
+            && let Some(parameter) = closure.function.lock().parameters.first()
+        {
+            parameter.0 .0.lock().0 = Some("self".to_string());
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use by_address::ByAddress;
+    use parking_lot::Mutex;
+    use triomphe::Arc;
+
+    use super::*;
+    use crate::{Assign, Closure, Function, Global, Local, MethodCall};
+
+    fn local(name: &str) -> RcLocal {
+        RcLocal::new(Local::new(Some(name.to_string())))
+    }
+
+    fn global(name: &str) -> RValue {
+        Global::new(name.as_bytes().to_vec()).into()
+    }
+
+    fn define(table: RValue, name: &str) -> Statement {
+        let function = Function {
+            parameters: vec![local("p")],
+            ..Default::default()
+        };
+        let closure = Closure {
+            function: ByAddress(Arc::new(Mutex::new(function))),
+            upvalues: Vec::new(),
+        };
+        Assign::new(
+            vec![Index::new(table, Literal::String(name.as_bytes().to_vec()).into()).into()],
+            vec![closure.into()],
+        )
+        .into()
+    }
+
+    fn call(table: RValue, name: &str) -> Statement {
+        MethodCall::new(table, name.to_string(), Vec::new()).into()
+    }
+
+    fn recover(block: Vec<Statement>) -> String {
+        let mut block = Block(block);
+        recover_methods(&mut block);
+        block.to_string()
+    }
+
+    #[test]
+    fn called_on_same_local() {
+        let t = local("t");
+        let block = vec![define(t.clone().into(), "m"), call(t.into(), "m")];
+        assert_eq!(recover(block), "function t:m() end\nt:m()");
+    }
+
+    #[test]
+    fn called_on_same_global() {
+        let block = vec![define(global("T"), "m"), call(global("T"), "m")];
+        assert_eq!(recover(block), "function T:m() end\nT:m()");
+    }
+
+    #[test]
+    fn called_on_other_table() {
+        // a method with the same name on another table says nothing about this function
+        let (t, u) = (local("t"), local("u"));
+        let block = vec![define(t.into(), "m"), call(u.clone().into(), "m")];
+        assert_eq!(recover(block), "function t.m(p) end\nu:m()");
+        let block = vec![define(global("T"), "m"), call(global("U"), "m")];
+        assert_eq!(recover(block), "function T.m(p) end\nU:m()");
+    }
+
+    #[test]
+    fn not_called_as_method() {
+        let t = local("t");
+        let block = vec![define(t.clone().into(), "m"), call(t.into(), "n")];
+        assert_eq!(recover(block), "function t.m(p) end\nt:n()");
+    }
+}
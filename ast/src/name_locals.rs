@@ -1,21 +1,38 @@
+use itertools::Either;
 use rustc_hash::FxHashSet;
 use triomphe::Arc;
 
-use crate::{Block, RValue, RcLocal, Statement, Traverse, Upvalue};
+use crate::{
+    formatter::Formatter, Assign, Block, LValue, Literal, RValue, RcLocal, Select, Statement,
+    Traverse, Upvalue,
+};
 
 struct Namer {
     rename: bool,
+    semantic: bool,
     counter: usize,
     upvalues: FxHashSet<RcLocal>,
+    // the names of the globals used anywhere, a local with the same name would shadow them
+    globals: FxHashSet<Vec<u8>>,
+    // the names of the locals in scope
+    scope: Vec<String>,
+    // the locals that didn't have a name before
+    unnamed: Vec<RcLocal>,
 }
 
 impl Namer {
-    fn name_local(&mut self, prefix: &str, local: &RcLocal) {
+    fn name_local(&mut self, prefix: &str, local: &RcLocal, hint: Option<String>) {
         let mut lock = local.0 .0.lock();
+        if lock.0.is_none() {
+            self.unnamed.push(local.clone());
+        }
         if self.rename || lock.0.is_none() {
             // TODO: hacky and slow
-            if Arc::count(&local.0 .0) == 1 {
+            // an unused `self` is still implicit in `function t:method()`
+            if Arc::count(&local.0 .0) == 1 && hint.as_deref() != Some("self") {
                 lock.0 = Some("_".to_string());
+            } else if let Some(hint) = hint {
+                lock.0 = Some(self.unique_name(hint));
             } else {
                 let prefix = prefix.to_string()
                     + if self.upvalues.contains(local) {
@@ -27,25 +44,197 @@ impl Namer {
                 self.counter += 1;
             }
         }
+        if let Some(name) = &lock.0 {
+            self.scope.push(name.clone());
+        }
+    }
+
+    // `hint`, or `hint2`, `hint3`, etc. if a local in scope or a global already has that name.
+    // locals that are out of scope can have the same name
+    fn unique_name(&self, hint: String) -> String {
+        let is_free = |name: &str| {
+            !self.scope.iter().any(|n| n == name) && !self.globals.contains(name.as_bytes())
+        };
+        if is_free(&hint) {
+            return hint;
+        }
+        (2..)
+            .map(|n| format!("{}{}", hint, n))
+            .find(|name| is_free(name))
+            .unwrap()
+    }
+
+    // turns `hint` into a name that is valid and can't be confused with a generated name
+    fn sanitize(hint: &[u8]) -> Option<String> {
+        let name = hint
+            .iter()
+            .filter(|c| c.is_ascii_alphanumeric() || **c == b'_')
+            .skip_while(|c| c.is_ascii_digit())
+            .map(|&c| c as char)
+            .collect::<String>();
+        let is_generated = |name: &str| {
+            let digits = name
+                .strip_prefix(['v', 'p'])
+                .map(|n| n.strip_prefix("_u_").unwrap_or(n));
+            name == "_" || digits.is_some_and(|d| d.bytes().all(|c| c.is_ascii_digit()))
+        };
+        if name.is_empty() || is_generated(&name) {
+            None
+        } else if !Formatter::<String>::is_valid_name(name.as_bytes()) {
+            // a reserved keyword
+            Some(name + "_")
+        } else {
+            Some(name)
+        }
+    }
+
+    // a name for a local from the value it's initialized with
+    fn value_hint(value: &RValue) -> Option<&[u8]> {
+        fn string_argument(arguments: &[RValue]) -> Option<&[u8]> {
+            match arguments.first() {
+                Some(RValue::Literal(Literal::String(string))) => Some(string),
+                _ => None,
+            }
+        }
+
+        match value {
+            // `game:GetService("Players")`, `character:FindFirstChild("Humanoid")`
+            RValue::MethodCall(method_call) | RValue::Select(Select::MethodCall(method_call))
+                if matches!(
+                    method_call.method.as_str(),
+                    "GetService"
+                        | "FindService"
+                        | "FindFirstChild"
+                        | "WaitForChild"
+                        | "FindFirstChildOfClass"
+                        | "FindFirstChildWhichIsA"
+                        | "FindFirstAncestor"
+                ) =>
+            {
+                string_argument(&method_call.arguments)
+            }
+            RValue::Call(call) | RValue::Select(Select::Call(call)) => match call.value.as_ref() {
+                // `require(script.Parent.Foo)`
+                RValue::Global(global) if global.0 == b"require" && call.arguments.len() == 1 => {
+                    Self::value_hint(&call.arguments[0])
+                }
+                // `Instance.new("Part")`
+                RValue::Index(index)
+                    if matches!(index.left.as_ref(), RValue::Global(global) if global.0 == b"Instance")
+                        && matches!(index.right.as_ref(), RValue::Literal(Literal::String(key)) if key == b"new") =>
+                {
+                    string_argument(&call.arguments)
+                }
+                _ => None,
+            },
+            // `x.Foo`
+            RValue::Index(index) => match index.right.as_ref() {
+                RValue::Literal(Literal::String(key)) => Some(key),
+                _ => None,
+            },
+            RValue::Commented(commented) => Self::value_hint(&commented.value),
+            _ => None,
+        }
+    }
+
+    // a name for a local from the first table field it's assigned to, `t.Foo = local`
+    fn field_hint<'a>(local: &RcLocal, rest: &'a [Statement]) -> Option<&'a [u8]> {
+        rest.iter().find_map(|statement| match statement.inner() {
+            Statement::Assign(Assign {
+                left,
+                right,
+                prefix: false,
+                ..
+            }) if left.len() == 1 && right.len() == 1 && right[0].as_local() == Some(local) => {
+                match &left[0] {
+                    LValue::Index(index) => match index.right.as_ref() {
+                        RValue::Literal(Literal::String(key)) => Some(key.as_slice()),
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+    }
+
+    // `for i, v in ipairs(t)`, `for k, v in pairs(t)`
+    fn iterator_hints(right: &[RValue]) -> &'static [&'static str] {
+        let iterator = match right.first() {
+            Some(RValue::Call(call) | RValue::Select(Select::Call(call))) => call.value.as_ref(),
+            Some(iterator) => iterator,
+            None => return &[],
+        };
+        match iterator {
+            RValue::Global(global) if global.0 == b"ipairs" => &["i", "v"],
+            RValue::Global(global) if global.0 == b"pairs" || global.0 == b"next" => &["k", "v"],
+            // luau's generalized iteration, `for k, v in t`
+            RValue::Local(_) | RValue::Index(_) if right.len() == 1 => &["k", "v"],
+            _ => &[],
+        }
+    }
+
+    // the debug name of the function in `local name = function() end`
+    fn function_name(assign: &Assign) -> Option<String> {
+        match (assign.left.as_slice(), assign.right.as_slice()) {
+            ([LValue::Local(_)], [RValue::Closure(closure)]) if assign.prefix => closure
+                .function
+                .lock()
+                .name
+                .as_ref()
+                .and_then(|name| Self::sanitize(name.as_bytes())),
+            _ => None,
+        }
     }
 
     fn name_locals(&mut self, block: &mut Block) {
-        for statement in &mut block.0 {
+        let scope = self.scope.len();
+        for index in 0..block.len() {
+            let (statement, rest) = block.0[index..].split_first_mut().unwrap();
+            let statement = statement.inner_mut();
+            // a local function is named before its body, so that the locals in it
+            // can't shadow it if it's recursive
+            let function_name = match statement {
+                Statement::Assign(assign) => Self::function_name(assign),
+                _ => None,
+            };
+            if let Some(name) = &function_name
+                && let Statement::Assign(assign) = statement
+            {
+                self.name_local("v", assign.left[0].as_local().unwrap(), Some(name.clone()));
+            }
             // TODO: traverse_rvalues
             statement.post_traverse_values(&mut |value| -> Option<()> {
-                if let itertools::Either::Right(RValue::Closure(closure)) = value {
+                if let Either::Right(RValue::Closure(closure)) = value {
                     let mut function = closure.function.lock();
-                    for param in &function.parameters {
-                        self.name_local("p", param);
+                    let scope = self.scope.len();
+                    for (i, param) in function.parameters.iter().enumerate() {
+                        // the first parameter of a method keeps its name
+                        let hint = (i == 0 && param.0 .0.lock().0.as_deref() == Some("self"))
+                            .then(|| "self".to_string());
+                        self.name_local("p", param, hint);
                     }
                     self.name_locals(&mut function.body);
+                    self.scope.truncate(scope);
                 };
                 None
             });
             match statement {
-                Statement::Assign(assign) if assign.prefix => {
-                    for lvalue in &assign.left {
-                        self.name_local("v", lvalue.as_local().unwrap());
+                Statement::Assign(assign) if assign.prefix && function_name.is_none() => {
+                    for (i, lvalue) in assign.left.iter().enumerate() {
+                        let local = lvalue.as_local().unwrap();
+                        let hint = if self.semantic {
+                            // `local a, b = f()` has no value for each local
+                            let value =
+                                (assign.left.len() == assign.right.len()).then(|| &assign.right[i]);
+                            value
+                                .and_then(Self::value_hint)
+                                .or_else(|| Self::field_hint(local, rest))
+                                .and_then(Self::sanitize)
+                        } else {
+                            None
+                        };
+                        self.name_local("v", local, hint);
                     }
                 }
                 Statement::If(r#if) => {
@@ -59,27 +248,45 @@ impl Namer {
                     self.name_locals(&mut repeat.block.lock());
                 }
                 Statement::NumericFor(numeric_for) => {
-                    self.name_local("v", &numeric_for.counter);
+                    let scope = self.scope.len();
+                    let hint = self.semantic.then(|| "i".to_string());
+                    self.name_local("v", &numeric_for.counter, hint);
                     self.name_locals(&mut numeric_for.block.lock());
+                    self.scope.truncate(scope);
                 }
                 Statement::GenericFor(generic_for) => {
-                    for res_local in &generic_for.res_locals {
-                        self.name_local("v", res_local);
+                    let scope = self.scope.len();
+                    let hints = if self.semantic {
+                        Self::iterator_hints(&generic_for.right)
+                    } else {
+                        &[]
+                    };
+                    for (i, res_local) in generic_for.res_locals.iter().enumerate() {
+                        let hint = hints.get(i).map(|h| h.to_string());
+                        self.name_local("v", res_local, hint);
                     }
                     self.name_locals(&mut generic_for.block.lock());
+                    self.scope.truncate(scope);
                 }
                 _ => {}
             }
         }
+        self.scope.truncate(scope);
     }
 
+    // also finds the globals that are used
     // TODO: does this need to be mut?
     fn find_upvalues(&mut self, block: &mut Block) {
         for statement in &mut block.0 {
             // TODO: traverse_values
             // TODO: doesnt need to be mut
             statement.post_traverse_values(&mut |value| -> Option<()> {
-                if let itertools::Either::Right(RValue::Closure(closure)) = value {
+                if let Either::Left(LValue::Global(global))
+                | Either::Right(RValue::Global(global)) = &value
+                {
+                    self.globals.insert(global.0.clone());
+                }
+                if let Either::Right(RValue::Closure(closure)) = value {
                     self.upvalues.extend(
                         closure
                             .upvalues
@@ -93,7 +300,7 @@ impl Namer {
                 };
                 None
             });
-            match statement {
+            match statement.inner_mut() {
                 Statement::If(r#if) => {
                     self.find_upvalues(&mut r#if.then_block.lock());
                     self.find_upvalues(&mut r#if.else_block.lock());
@@ -116,12 +323,238 @@ impl Namer {
     }
 }
 
-pub fn name_locals(block: &mut Block, rename: bool) {
+/// Names the locals in `block` `v1`, `p2`, `v_u_3`, etc., only unnamed locals unless `rename` is set.
+/// Local functions are named after their debug name.
+/// If `semantic` is set, locals are named after the values they hold where possible,
+/// like `Players` for `game:GetService("Players")` and `i, v` for `ipairs`.
+///
+/// Returns the locals that were unnamed, so that provisional names can be removed again.
+pub fn name_locals(block: &mut Block, rename: bool, semantic: bool) -> Vec<RcLocal> {
     let mut namer = Namer {
         rename,
+        semantic,
         counter: 1,
         upvalues: FxHashSet::default(),
+        globals: FxHashSet::default(),
+        scope: Vec::new(),
+        unnamed: Vec::new(),
     };
     namer.find_upvalues(block);
     namer.name_locals(block);
+    namer.unnamed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Call, GenericFor, Global, If, Index, Local, MethodCall};
+
+    fn declare(local: &RcLocal, value: RValue) -> Statement {
+        let mut assign = Assign::new(vec![local.clone().into()], vec![value]);
+        assign.prefix = true;
+        assign.into()
+    }
+
+    #[test]
+    fn unnamed_locals_returned() {
+        let x = RcLocal::default();
+        let y = RcLocal::new(Local::new(Some("y".to_string())));
+        let mut block = Block(vec![
+            declare(&x, Literal::Number(1.0).into()),
+            declare(&y, x.clone().into()),
+        ]);
+        assert_eq!(name_locals(&mut block, false, false), vec![x]);
+        assert_eq!(block.to_string(), "local v1 = 1\nlocal y = v1");
+    }
+
+    fn global(name: &str) -> RValue {
+        Global::new(name.as_bytes().to_vec()).into()
+    }
+
+    fn string(string: &str) -> RValue {
+        Literal::String(string.as_bytes().to_vec()).into()
+    }
+
+    fn index(left: RValue, key: &str) -> RValue {
+        Index::new(left, string(key)).into()
+    }
+
+    fn call(value: RValue, arguments: Vec<RValue>) -> RValue {
+        Call::new(value, arguments).into()
+    }
+
+    fn print(values: Vec<RValue>) -> Statement {
+        Call::new(global("print"), values).into()
+    }
+
+    fn sanitize(hint: &str) -> Option<String> {
+        Namer::sanitize(hint.as_bytes())
+    }
+
+    fn value_hint(value: RValue) -> Option<String> {
+        Namer::value_hint(&value).map(|hint| String::from_utf8(hint.to_vec()).unwrap())
+    }
+
+    // names the locals with hints and formats the block
+    fn semantic(statements: Vec<Statement>) -> String {
+        let mut block = Block(statements);
+        name_locals(&mut block, false, true);
+        block.to_string()
+    }
+
+    #[test]
+    fn service_hint() {
+        let get_service = MethodCall::new(
+            global("game"),
+            "GetService".to_string(),
+            vec![string("Players")],
+        );
+        assert_eq!(value_hint(get_service.into()).as_deref(), Some("Players"));
+        let other = MethodCall::new(global("game"), "Get".to_string(), vec![string("Players")]);
+        assert_eq!(value_hint(other.into()), None);
+    }
+
+    #[test]
+    fn require_hint() {
+        let module = index(index(global("script"), "Parent"), "Foo");
+        assert_eq!(
+            value_hint(call(global("require"), vec![module])).as_deref(),
+            Some("Foo")
+        );
+    }
+
+    #[test]
+    fn instance_hint() {
+        let new = index(global("Instance"), "new");
+        assert_eq!(
+            value_hint(call(new, vec![string("Part")])).as_deref(),
+            Some("Part")
+        );
+        assert_eq!(value_hint(call(global("f"), vec![string("Part")])), None);
+    }
+
+    #[test]
+    fn index_hint() {
+        assert_eq!(
+            value_hint(index(global("t"), "Foo")).as_deref(),
+            Some("Foo")
+        );
+        let computed = Index::new(global("t"), global("k"));
+        assert_eq!(value_hint(computed.into()), None);
+    }
+
+    #[test]
+    fn field_hint() {
+        let table = RcLocal::default();
+        let field = Index::new(global("t"), string("Foo"));
+        let statements = [Assign::new(vec![field.into()], vec![table.clone().into()]).into()];
+        assert_eq!(Namer::field_hint(&table, &statements), Some(&b"Foo"[..]));
+        assert_eq!(Namer::field_hint(&RcLocal::default(), &statements), None);
+    }
+
+    #[test]
+    fn iterator_hints() {
+        let ipairs = call(global("ipairs"), vec![global("t")]);
+        assert_eq!(Namer::iterator_hints(&[ipairs]), ["i", "v"]);
+        let pairs = call(global("pairs"), vec![global("t")]);
+        assert_eq!(Namer::iterator_hints(&[pairs]), ["k", "v"]);
+        let next = [global("next"), global("t")];
+        assert_eq!(Namer::iterator_hints(&next), ["k", "v"]);
+        let table = [RcLocal::default().into()];
+        assert_eq!(Namer::iterator_hints(&table), ["k", "v"]);
+        let other = call(global("f"), vec![global("t")]);
+        assert!(Namer::iterator_hints(&[other]).is_empty());
+    }
+
+    #[test]
+    fn sanitized_to_identifier() {
+        assert_eq!(sanitize("Foo Bar").as_deref(), Some("FooBar"));
+        assert_eq!(sanitize("2D-Model").as_deref(), Some("DModel"));
+        assert_eq!(sanitize("value").as_deref(), Some("value"));
+        assert_eq!(sanitize("!!"), None);
+    }
+
+    #[test]
+    fn sanitized_keyword() {
+        assert_eq!(sanitize("end").as_deref(), Some("end_"));
+        assert_eq!(sanitize("local").as_deref(), Some("local_"));
+    }
+
+    #[test]
+    fn generated_names_rejected() {
+        for hint in ["v1", "p2", "v_u_3", "_"] {
+            assert_eq!(sanitize(hint), None, "{}", hint);
+        }
+    }
+
+    #[test]
+    fn global_not_shadowed() {
+        let players = RcLocal::default();
+        let statements = vec![
+            declare(&players, index(global("game"), "Players")),
+            print(vec![players.clone().into(), global("Players")]),
+        ];
+        assert_eq!(
+            semantic(statements),
+            "local Players2 = game.Players\nprint(Players2, Players)"
+        );
+    }
+
+    #[test]
+    fn outer_local_not_shadowed() {
+        let (a, b) = (RcLocal::default(), RcLocal::default());
+        let statements = vec![
+            declare(&a, index(global("t"), "Foo")),
+            declare(&b, index(global("u"), "Foo")),
+            print(vec![a.clone().into(), b.clone().into()]),
+        ];
+        assert_eq!(
+            semantic(statements),
+            "local Foo = t.Foo\nlocal Foo2 = u.Foo\nprint(Foo, Foo2)"
+        );
+    }
+
+    #[test]
+    fn name_reused_out_of_scope() {
+        let (a, b) = (RcLocal::default(), RcLocal::default());
+        let branch = |local: &RcLocal| {
+            Block(vec![
+                declare(local, index(global("t"), "Foo")),
+                print(vec![local.clone().into()]),
+            ])
+        };
+        let r#if = If::new(global("c"), branch(&a), branch(&b));
+        assert_eq!(
+            semantic(vec![r#if.into()]),
+            "if c then\n\tlocal Foo = t.Foo\n\tprint(Foo)\nelse\n\tlocal Foo = t.Foo\n\tprint(Foo)\nend"
+        );
+    }
+
+    #[test]
+    fn nested_loops_not_shadowed() {
+        let (i, v, j, w) = (
+            RcLocal::default(),
+            RcLocal::default(),
+            RcLocal::default(),
+            RcLocal::default(),
+        );
+        let inner = GenericFor::new(
+            vec![j.clone(), w.clone()],
+            vec![call(global("ipairs"), vec![v.clone().into()])],
+            Block(vec![print(vec![
+                i.clone().into(),
+                j.clone().into(),
+                w.clone().into(),
+            ])]),
+        );
+        let outer = GenericFor::new(
+            vec![i.clone(), v.clone()],
+            vec![call(global("ipairs"), vec![global("t")])],
+            Block(vec![inner.into()]),
+        );
+        assert_eq!(
+            semantic(vec![outer.into()]),
+            "for i, v in ipairs(t) do\n\tfor i2, v2 in ipairs(v) do\n\t\tprint(i, i2, v2)\n\tend\nend"
+        );
+    }
 }
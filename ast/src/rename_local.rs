@@ -0,0 +1,372 @@
+use std::fmt;
+
+use itertools::Either;
+
+use crate::{formatter::Formatter, Block, LValue, LocalRw, RValue, RcLocal, Statement, Traverse};
+
+/// Why a local can't be given a name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conflict {
+    /// The name is a keyword or isn't an identifier.
+    InvalidName,
+    /// The local isn't declared or used in the block.
+    NotFound,
+    /// A use of the local or of this other local would refer to the wrong one,
+    /// because one of them shadows the other there.
+    Local(RcLocal),
+    /// A use of a global with the name would refer to the local instead.
+    Global,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidName => write!(f, "the name is not a valid identifier"),
+            Self::NotFound => write!(f, "the local is not in the block"),
+            Self::Local(local) => write!(f, "the name conflicts with the local {}", local),
+            Self::Global => write!(f, "the name conflicts with a global"),
+        }
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+struct Renamer<'a> {
+    local: &'a RcLocal,
+    name: &'a str,
+    // the locals in scope, innermost last
+    scope: Vec<RcLocal>,
+    declared: bool,
+    used: bool,
+}
+
+impl Renamer<'_> {
+    fn has_name(&self, local: &RcLocal) -> bool {
+        local.0 .0.lock().0.as_deref() == Some(self.name)
+    }
+
+    fn position(&self, local: &RcLocal) -> Option<usize> {
+        self.scope.iter().rposition(|l| l == local)
+    }
+
+    fn declare(&mut self, local: &RcLocal) {
+        if local == self.local {
+            self.declared = true;
+        }
+        self.scope.push(local.clone());
+    }
+
+    // after the rename, `local` has to still refer to the same local here
+    fn check_use(&mut self, local: &RcLocal) -> Result<(), Conflict> {
+        if local == self.local {
+            self.used = true;
+            // a local with the name declared after it would shadow it
+            if let Some(position) = self.position(local)
+                && let Some(shadow) = self.scope[position + 1..]
+                    .iter()
+                    .find(|&l| l != local && self.has_name(l))
+            {
+                return Err(Conflict::Local(shadow.clone()));
+            }
+        } else if self.has_name(local)
+            && let Some(position) = self.position(self.local)
+            && self.position(local).is_none_or(|p| p < position)
+        {
+            // it would shadow a local with the name declared before it
+            return Err(Conflict::Local(local.clone()));
+        }
+        Ok(())
+    }
+
+    fn check_global(&self, global: &[u8]) -> Result<(), Conflict> {
+        if global == self.name.as_bytes() && self.position(self.local).is_some() {
+            return Err(Conflict::Global);
+        }
+        Ok(())
+    }
+
+    // checks the locals and globals used in `statement` and the closures in it,
+    // but not its blocks
+    fn check_values(&mut self, statement: &mut Statement) -> Result<(), Conflict> {
+        for local in statement.values_read() {
+            self.check_use(local)?;
+        }
+        statement
+            .post_traverse_values(&mut |value| -> Option<Result<(), Conflict>> {
+                match value {
+                    Either::Left(LValue::Global(global))
+                    | Either::Right(RValue::Global(global)) => {
+                        self.check_global(&global.0).err().map(Err)
+                    }
+                    Either::Right(RValue::Closure(closure)) => {
+                        let mut function = closure.function.lock();
+                        let scope = self.scope.len();
+                        for parameter in &function.parameters {
+                            self.declare(parameter);
+                        }
+                        if let Err(conflict) = self.check_block(&mut function.body) {
+                            return Some(Err(conflict));
+                        }
+                        self.scope.truncate(scope);
+                        None
+                    }
+                    _ => None,
+                }
+            })
+            .unwrap_or(Ok(()))
+    }
+
+    fn check_block(&mut self, block: &mut Block) -> Result<(), Conflict> {
+        let scope = self.scope.len();
+        self.check_statements(block)?;
+        self.scope.truncate(scope);
+        Ok(())
+    }
+
+    // the locals declared in `block` are left in scope
+    fn check_statements(&mut self, block: &mut Block) -> Result<(), Conflict> {
+        for statement in block.iter_mut() {
+            let statement = statement.inner_mut();
+            match statement {
+                Statement::Assign(assign) if assign.prefix => {
+                    // `local function f()` can use `f` in its body
+                    let function =
+                        assign.left.len() == 1 && matches!(assign.right[..], [RValue::Closure(_)]);
+                    let locals = assign
+                        .left
+                        .iter()
+                        .map(|l| l.as_local().unwrap().clone())
+                        .collect::<Vec<_>>();
+                    if function {
+                        self.declare(&locals[0]);
+                    }
+                    self.check_values(statement)?;
+                    if !function {
+                        for local in &locals {
+                            self.declare(local);
+                        }
+                    }
+                }
+                Statement::NumericFor(numeric_for) => {
+                    let (counter, block) = (numeric_for.counter.clone(), numeric_for.block.clone());
+                    self.check_values(statement)?;
+                    let scope = self.scope.len();
+                    self.declare(&counter);
+                    self.check_block(&mut block.lock())?;
+                    self.scope.truncate(scope);
+                }
+                Statement::GenericFor(generic_for) => {
+                    let (res_locals, block) =
+                        (generic_for.res_locals.clone(), generic_for.block.clone());
+                    self.check_values(statement)?;
+                    let scope = self.scope.len();
+                    for local in &res_locals {
+                        self.declare(local);
+                    }
+                    self.check_block(&mut block.lock())?;
+                    self.scope.truncate(scope);
+                }
+                Statement::Repeat(repeat) => {
+                    // the condition can use the locals in the block
+                    let block = repeat.block.clone();
+                    let scope = self.scope.len();
+                    self.check_statements(&mut block.lock())?;
+                    self.check_values(statement)?;
+                    self.scope.truncate(scope);
+                }
+                _ => {
+                    for local in statement.values_written() {
+                        self.check_use(local)?;
+                    }
+                    self.check_values(statement)?;
+                    match statement {
+                        Statement::If(r#if) => {
+                            self.check_block(&mut r#if.then_block.lock())?;
+                            self.check_block(&mut r#if.else_block.lock())?;
+                        }
+                        Statement::While(r#while) => {
+                            self.check_block(&mut r#while.block.lock())?;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Renames `local` to `new_name` if every use of it, and of the other locals and globals
+/// in `block` and the closures in it, would still refer to the same variable.
+/// `local` is left as is if there's a conflict.
+pub fn rename_local(block: &mut Block, local: &RcLocal, new_name: &str) -> Result<(), Conflict> {
+    if !Formatter::<String>::is_valid_name(new_name.as_bytes()) {
+        return Err(Conflict::InvalidName);
+    }
+    let mut renamer = Renamer {
+        local,
+        name: new_name,
+        scope: Vec::new(),
+        declared: false,
+        used: false,
+    };
+    renamer.check_block(block)?;
+    if !renamer.declared {
+        if !renamer.used {
+            return Err(Conflict::NotFound);
+        }
+        // it's declared outside of the block, so it's in scope everywhere in it
+        renamer.scope.push(local.clone());
+        renamer.check_block(block)?;
+    }
+    local.0 .0.lock().0 = Some(new_name.to_string());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use by_address::ByAddress;
+    use parking_lot::Mutex;
+    use triomphe::Arc;
+
+    use super::*;
+    use crate::{
+        comment_before, Assign, Binary, BinaryOperation, Call, Closure, Comment, Function, Global,
+        If, Literal, Local, Repeat, Return, Upvalue,
+    };
+
+    fn local(name: &str) -> RcLocal {
+        RcLocal::new(Local::new(Some(name.to_string())))
+    }
+
+    fn declare(local: &RcLocal, value: RValue) -> Statement {
+        let mut assign = Assign::new(vec![local.clone().into()], vec![value]);
+        assign.prefix = true;
+        assign.into()
+    }
+
+    fn print(values: Vec<RValue>) -> Statement {
+        Call::new(Global::new(b"print".to_vec()).into(), values).into()
+    }
+
+    fn rename(block: Vec<Statement>, local: &RcLocal, name: &str) -> Result<String, Conflict> {
+        let mut block = Block(block);
+        rename_local(&mut block, local, name)?;
+        Ok(block.to_string())
+    }
+
+    #[test]
+    fn renamed() {
+        let x = local("x");
+        let block = vec![
+            declare(&x, Literal::Number(1.0).into()),
+            print(vec![x.clone().into()]),
+        ];
+        assert_eq!(
+            rename(block, &x, "y"),
+            Ok("local y = 1\nprint(y)".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_name() {
+        let x = local("x");
+        let block = vec![declare(&x, Literal::Number(1.0).into())];
+        assert_eq!(rename(block.clone(), &x, "end"), Err(Conflict::InvalidName));
+        assert_eq!(rename(block, &x, "1a"), Err(Conflict::InvalidName));
+        assert_eq!(x.to_string(), "x");
+    }
+
+    #[test]
+    fn not_found() {
+        let (x, y) = (local("x"), local("y"));
+        let block = vec![declare(&x, Literal::Number(1.0).into())];
+        assert_eq!(rename(block, &y, "z"), Err(Conflict::NotFound));
+    }
+
+    #[test]
+    fn shadowed_by_inner_local() {
+        // `x` can't be used after a local with its new name in the same scope
+        let (x, y) = (local("x"), local("y"));
+        let block = vec![
+            declare(&x, Literal::Number(1.0).into()),
+            If::new(
+                Global::new(b"c".to_vec()).into(),
+                Block(vec![
+                    declare(&y, Literal::Number(2.0).into()),
+                    print(vec![x.clone().into(), y.clone().into()]),
+                ]),
+                Block::default(),
+            )
+            .into(),
+        ];
+        assert_eq!(rename(block, &x, "y"), Err(Conflict::Local(y)));
+        assert_eq!(x.to_string(), "x");
+    }
+
+    #[test]
+    fn shadowed_in_closure() {
+        let (x, y) = (local("x"), local("y"));
+        let function = Function {
+            body: Block(vec![Return::new(vec![x.clone().into()]).into()]),
+            ..Default::default()
+        };
+        let closure = Closure {
+            function: ByAddress(Arc::new(Mutex::new(function))),
+            upvalues: vec![Upvalue::Ref(x.clone())],
+        };
+        let block = vec![
+            declare(&x, Literal::Number(1.0).into()),
+            declare(&y, Literal::Number(2.0).into()),
+            print(vec![closure.into(), y.clone().into()]),
+        ];
+        assert_eq!(rename(block, &x, "y"), Err(Conflict::Local(y)));
+    }
+
+    #[test]
+    fn global_clash() {
+        let x = local("x");
+        let block = vec![
+            declare(&x, Literal::Number(1.0).into()),
+            print(vec![Global::new(b"y".to_vec()).into(), x.clone().into()]),
+        ];
+        assert_eq!(rename(block, &x, "y"), Err(Conflict::Global));
+    }
+
+    #[test]
+    fn repeat_condition_uses_body_local() {
+        // the locals declared in a `repeat` loop are in scope in its condition
+        let (x, y) = (local("x"), local("y"));
+        let repeat = |x: &RcLocal| -> Statement {
+            Repeat::new(
+                Binary::new(x.clone().into(), y.clone().into(), BinaryOperation::Equal).into(),
+                Block(vec![declare(x, Literal::Number(1.0).into())]),
+            )
+            .into()
+        };
+        let block = vec![declare(&y, Literal::Number(2.0).into()), repeat(&x)];
+        assert_eq!(rename(block, &x, "y"), Err(Conflict::Local(y.clone())));
+        let block = vec![declare(&y, Literal::Number(2.0).into()), repeat(&x)];
+        assert_eq!(
+            rename(block, &x, "z"),
+            Ok("local y = 2\nrepeat\n\tlocal z = 1\nuntil z == y".to_string())
+        );
+    }
+
+    #[test]
+    fn commented_if() {
+        let (x, y) = (local("x"), local("y"));
+        let mut r#if: Statement = If::new(
+            Global::new(b"c".to_vec()).into(),
+            Block(vec![
+                declare(&y, Literal::Number(2.0).into()),
+                print(vec![x.clone().into()]),
+            ]),
+            Block::default(),
+        )
+        .into();
+        comment_before(&mut r#if, Comment::new("a".to_string()));
+        let block = vec![declare(&x, Literal::Number(1.0).into()), r#if];
+        assert_eq!(rename(block, &x, "y"), Err(Conflict::Local(y)));
+    }
+}
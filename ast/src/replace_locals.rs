@@ -26,7 +26,7 @@ pub fn replace_locals<H: std::hash::BuildHasher>(
             };
             None
         });
-        match statement {
+        match statement.inner_mut() {
             Statement::If(r#if) => {
                 replace_locals(&mut r#if.then_block.lock(), map);
                 replace_locals(&mut r#if.else_block.lock(), map);
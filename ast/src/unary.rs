@@ -395,8 +395,9 @@ impl Unary {
                     })
                 ) || matches!(
                     *self.value,
+                    // `-1` and `-(0 / 0)`
                     RValue::Literal(Literal::Number(value))
-                        if value.is_finite() && value.is_sign_negative()
+                        if value.is_sign_negative() != value.is_nan()
                 )))
     }
 }
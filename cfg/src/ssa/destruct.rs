@@ -115,11 +115,15 @@ impl<'a> Destructor<'a> {
         for node in self.function.graph().node_indices().collect::<Vec<_>>() {
             let liveness = &self.liveness[&node];
             let block = self.function.block_mut(node).unwrap();
-            block.insert(
-                0,
-                ast::Comment::new(liveness.live_in.iter().join(", ")).into(),
-            );
-            block.push(ast::Comment::new(liveness.live_out.iter().join(", ")).into());
+            let live_in = ast::Comment::new(liveness.live_in.iter().join(", "));
+            let live_out = ast::Comment::new(liveness.live_out.iter().join(", "));
+            if block.is_empty() {
+                block.push(live_in.into());
+                block.push(live_out.into());
+            } else {
+                ast::comment_before(block.first_mut().unwrap(), live_in);
+                ast::comment_after(block.last_mut().unwrap(), live_out);
+            }
         }
     }
 
@@ -86,6 +86,18 @@ impl<'a> Inliner<'a> {
                                     };
                                     return Some(true);
                                 }
+                                // only one of the values is evaluated
+                                ast::RValue::IfExpression(if_expression)
+                                    if new_rvalue_has_side_effects
+                                        && if_expression
+                                            .then_value
+                                            .values_read()
+                                            .into_iter()
+                                            .chain(if_expression.else_value.values_read())
+                                            .contains(read) =>
+                                {
+                                    return Some(false);
+                                }
                                 _ => {}
                             }
                         }
@@ -268,7 +268,9 @@ fn match_conditional_sequence(
     }
 }
 
-pub fn structure_conditionals(function: &mut Function) -> bool {
+/// Structures conditionals that can be expressed without branching.
+/// `if_expressions` allows using `if a then b else c` when `and`/`or` can't express it exactly.
+pub fn structure_conditionals(function: &mut Function, if_expressions: bool) -> bool {
     let mut did_structure = false;
     // TODO: does this need to be in dfs post order?
     let mut dfs = DfsPostOrder::new(function.graph(), function.entry().unwrap());
@@ -276,7 +278,7 @@ pub fn structure_conditionals(function: &mut Function) -> bool {
         if simplify_condition(function, node) {
             did_structure = true;
         }
-        if structure_bool_conditional(function, node) {
+        if structure_bool_conditional(function, node, if_expressions) {
             did_structure = true;
         }
 
@@ -442,11 +444,57 @@ fn make_bool_conditional(
     }
 }
 
-// TODO: `return if g then true else false` in luau?
+// `if c then a else b`, for values that can't be expressed exactly with `and`/`or`
+fn make_if_expression(
+    function: &mut Function,
+    node: NodeIndex,
+    then_value: ast::RValue,
+    else_value: ast::RValue,
+) -> Option<ast::RValue> {
+    if then_value.has_side_effects() || else_value.has_side_effects() {
+        return None;
+    }
+    let block = function.block_mut(node).unwrap();
+    let r#if = block.last_mut().unwrap().as_if_mut().unwrap();
+    let condition =
+        std::mem::replace(&mut r#if.condition, ast::Literal::Nil.into()).reduce_condition();
+    Some(
+        if let ast::RValue::Unary(ast::Unary {
+            box value,
+            operation: ast::UnaryOperation::Not,
+        }) = condition
+        {
+            ast::IfExpression::new(value, else_value, then_value)
+        } else {
+            ast::IfExpression::new(condition, then_value, else_value)
+        }
+        .into(),
+    )
+}
+
+fn make_conditional_value(
+    function: &mut Function,
+    node: NodeIndex,
+    then_value: ast::RValue,
+    else_value: ast::RValue,
+    if_expressions: bool,
+) -> Option<ast::RValue> {
+    // TODO: unnecessary clones
+    make_bool_conditional(function, node, then_value.clone(), else_value.clone()).or_else(|| {
+        if_expressions
+            .then(|| make_if_expression(function, node, then_value, else_value))
+            .flatten()
+    })
+}
+
 // local a; if g then a = true else a = false end; return a -> return g and true or false
 // local a; if g then a = false else a = true end; return a -> return not g
 // local a; if g == 1 then a = true else a = false end; return a -> return g == 1
-fn structure_bool_conditional(function: &mut Function, node: NodeIndex) -> bool {
+fn structure_bool_conditional(
+    function: &mut Function,
+    node: NodeIndex,
+    if_expressions: bool,
+) -> bool {
     let match_triangle = |assigner, next, next_args: FxHashMap<ast::RcLocal, ast::RValue>| {
         if let Some(edge_to_next) = function.unconditional_edge(assigner)
             && edge_to_next.target() == next
@@ -489,7 +537,9 @@ fn structure_bool_conditional(function: &mut Function, node: NodeIndex) -> bool
                 let then_value = then_value.clone();
                 let else_value = else_value.clone();
 
-                if let Some(res) = make_bool_conditional(function, node, then_value, else_value) {
+                if let Some(res) =
+                    make_conditional_value(function, node, then_value, else_value, if_expressions)
+                {
                     function
                         .graph_mut()
                         .edge_weight_mut(then_edge)
@@ -537,7 +587,9 @@ fn structure_bool_conditional(function: &mut Function, node: NodeIndex) -> bool
                 else_edge.id(),
             );
             let res_local = res_local.clone();
-            if let Some(res) = make_bool_conditional(function, node, then_value, else_value) {
+            if let Some(res) =
+                make_conditional_value(function, node, then_value, else_value, if_expressions)
+            {
                 function
                     .graph_mut()
                     .edge_weight_mut(then_edge)
@@ -586,7 +638,9 @@ fn structure_bool_conditional(function: &mut Function, node: NodeIndex) -> bool
                 function.unconditional_edge(else_block).unwrap().id(),
             );
             let res_local = res_local.clone();
-            if let Some(res) = make_bool_conditional(function, node, then_value, else_value) {
+            if let Some(res) =
+                make_conditional_value(function, node, then_value, else_value, if_expressions)
+            {
                 function
                     .graph_mut()
                     .edge_weight_mut(then_edge)
@@ -643,7 +697,9 @@ fn structure_bool_conditional(function: &mut Function, node: NodeIndex) -> bool
                 function.unconditional_edge(then_block).unwrap().id(),
                 function.unconditional_edge(else_block).unwrap().id(),
             );
-            if let Some(res) = make_bool_conditional(function, node, then_value, else_value) {
+            if let Some(res) =
+                make_conditional_value(function, node, then_value, else_value, if_expressions)
+            {
                 function
                     .graph_mut()
                     .edge_weight_mut(then_edge)
@@ -700,7 +756,9 @@ fn structure_bool_conditional(function: &mut Function, node: NodeIndex) -> bool
             let then_value = then_value.clone();
             let else_value = else_value.clone();
 
-            if let Some(res) = make_bool_conditional(function, node, then_value, else_value) {
+            if let Some(res) =
+                make_conditional_value(function, node, then_value, else_value, if_expressions)
+            {
                 function.remove_block(then_target);
                 function.remove_block(else_target);
                 let block = function.block_mut(node).unwrap();
@@ -957,3 +1015,90 @@ pub fn structure_jumps(function: &mut Function, dominators: &Dominators<NodeInde
     }
     did_structure
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(name: &str) -> ast::RValue {
+        ast::RcLocal::new(ast::Local::new(Some(name.to_string()))).into()
+    }
+
+    fn call(name: &str) -> ast::RValue {
+        ast::Call::new(local(name), vec![]).into()
+    }
+
+    // a function with a single block ending in `if condition`
+    fn branch(condition: ast::RValue) -> (Function, NodeIndex) {
+        let mut function = Function::new(0);
+        let node = function.new_block();
+        function.set_entry(node);
+        function
+            .block_mut(node)
+            .unwrap()
+            .push(ast::If::new(condition, ast::Block::default(), ast::Block::default()).into());
+        (function, node)
+    }
+
+    fn conditional_value(
+        condition: ast::RValue,
+        then_value: ast::RValue,
+        else_value: ast::RValue,
+        if_expressions: bool,
+    ) -> Option<String> {
+        let (mut function, node) = branch(condition);
+        make_conditional_value(&mut function, node, then_value, else_value, if_expressions)
+            .map(|value| value.to_string())
+    }
+
+    #[test]
+    fn if_expression() {
+        let (mut function, node) = branch(local("c"));
+        let value = make_if_expression(&mut function, node, local("a"), local("b")).unwrap();
+        assert_eq!(value.to_string(), "if c then a else b");
+    }
+
+    #[test]
+    fn if_expression_negated_condition() {
+        let condition = ast::Unary::new(local("c"), ast::UnaryOperation::Not).into();
+        let (mut function, node) = branch(condition);
+        let value = make_if_expression(&mut function, node, local("a"), local("b")).unwrap();
+        assert_eq!(value.to_string(), "if c then b else a");
+    }
+
+    #[test]
+    fn if_expression_with_side_effects() {
+        let (mut function, node) = branch(local("c"));
+        assert!(make_if_expression(&mut function, node, call("f"), local("b")).is_none());
+        // the condition is left in place
+        let block = function.block(node).unwrap();
+        let condition = &block.last().unwrap().as_if().unwrap().condition;
+        assert_eq!(condition.to_string(), "c");
+    }
+
+    #[test]
+    fn conditional_value_prefers_and_or() {
+        let then_value = ast::Literal::Number(1.0).into();
+        let else_value = ast::Literal::Number(2.0).into();
+        assert_eq!(
+            conditional_value(local("c"), then_value, else_value, true).as_deref(),
+            Some("c and 1 or 2")
+        );
+    }
+
+    #[test]
+    fn conditional_value_falls_back_to_if_expression() {
+        assert_eq!(
+            conditional_value(local("c"), local("a"), local("b"), true).as_deref(),
+            Some("if c then a else b")
+        );
+    }
+
+    #[test]
+    fn conditional_value_without_if_expressions() {
+        assert_eq!(
+            conditional_value(local("c"), local("a"), local("b"), false),
+            None
+        );
+    }
+}
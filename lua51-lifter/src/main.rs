@@ -2,8 +2,8 @@
 #![feature(let_chains)]
 
 use ast::{
-    local_declarations::LocalDeclarer, name_locals::name_locals, replace_locals::replace_locals,
-    Traverse,
+    formatter::Formatter, local_declarations::LocalDeclarer, name_locals::name_locals,
+    replace_locals::replace_locals, Dialect, Traverse, Unsupported,
 };
 use by_address::ByAddress;
 use cfg::ssa::{
@@ -53,7 +53,23 @@ fn main() -> anyhow::Result<()> {
     input.read_exact(&mut buffer)?;
 
     let start = Instant::now();
-    let chunk = Chunk::parse(&buffer).unwrap().1;
+    let (res, unsupported) = decompile(&buffer)?;
+    let duration = start.elapsed();
+
+    // TODO: use BufWriter?
+    let mut out = File::create(path.with_extension("dec.51.lua").file_name().unwrap())?;
+    writeln!(out, "-- decompiled by Sentinel (took {:?})", duration)?;
+    for unsupported in unsupported {
+        writeln!(out, "-- warning: {}", unsupported)?;
+    }
+    writeln!(out, "{}", res)?;
+
+    Ok(())
+}
+
+// decompiles a chunk, also returning the constructs in the output that Lua 5.1 can't represent
+fn decompile(buffer: &[u8]) -> anyhow::Result<(String, Vec<Unsupported>)> {
+    let chunk = Chunk::parse(buffer).unwrap().1;
     let mut lifted = Vec::new();
     let (function, upvalues) = Lifter::lift(&chunk.function, &mut lifted);
     lifted.push((Arc::<Mutex<_>>::default(), function, upvalues));
@@ -96,7 +112,7 @@ fn main() -> anyhow::Result<()> {
 
                 ssa::inline::inline(&mut function, &local_to_group, &upvalue_to_group);
 
-                if structure_conditionals(&mut function)
+                if structure_conditionals(&mut function, false)
                 // || {
                 //     let post_dominators = post_dominators(function.graph_mut());
                 //     structure_for_loops(&mut function, &dominators, &post_dominators)
@@ -122,7 +138,15 @@ fn main() -> anyhow::Result<()> {
 
             let params = std::mem::take(&mut function.parameters);
             let is_variadic = function.is_variadic;
-            let block = Arc::new(restructure::lift(function).into());
+            // lua 5.1 has neither goto nor continue, they are lowered to loops
+            let (block, _) = restructure::lift_with_options(
+                function,
+                restructure::Options {
+                    eliminate_gotos: true,
+                    ..Default::default()
+                },
+            );
+            let block = Arc::new(block.into());
             LocalDeclarer::default().declare_locals(
                 // TODO: why does block.clone() not work?
                 Arc::clone(&block),
@@ -143,16 +167,11 @@ fn main() -> anyhow::Result<()> {
     upvalues.remove(&main);
     let mut body = Arc::try_unwrap(main.0).unwrap().into_inner().body;
     link_upvalues(&mut body, &mut upvalues);
-    name_locals(&mut body, true);
-    let res = body.to_string();
-    let duration = start.elapsed();
-
-    // TODO: use BufWriter?
-    let mut out = File::create(path.with_extension("dec.51.lua").file_name().unwrap())?;
-    writeln!(out, "-- decompiled by Sentinel (took {:?})", duration)?;
-    writeln!(out, "{}", res)?;
-
-    Ok(())
+    name_locals(&mut body, true, false);
+    let unsupported = Dialect::Lua51.validate(&body);
+    let mut res = String::new();
+    Formatter::format(&body, &mut res, Dialect::Lua51, Default::default())?;
+    Ok((res, unsupported))
 }
 
 fn link_upvalues(
@@ -182,7 +201,7 @@ fn link_upvalues(
                 replace_locals(&mut function.body, &local_map);
             }
         });
-        match stat {
+        match stat.inner_mut() {
             ast::Statement::If(r#if) => {
                 link_upvalues(&mut r#if.then_block.lock(), upvalues);
                 link_upvalues(&mut r#if.else_block.lock(), upvalues);
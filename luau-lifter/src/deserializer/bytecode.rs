@@ -1,7 +1,14 @@
+use std::ops::RangeInclusive;
+
 use nom::{bytes::complete::take, number::complete::le_u8, IResult};
 
 use super::chunk::Chunk;
 
+/// Luau bytecode versions the deserializer understands.
+pub const SUPPORTED_VERSIONS: RangeInclusive<u8> = 4..=6;
+/// Luau type information versions the deserializer understands.
+pub const SUPPORTED_TYPES_VERSIONS: RangeInclusive<u8> = 0..=3;
+
 #[derive(Debug)]
 pub enum Bytecode {
     Error(String),
@@ -19,11 +26,27 @@ impl Bytecode {
                     Bytecode::Error(String::from_utf8_lossy(error_msg).to_string()),
                 ))
             }
-            4..=6 => {
-                let (input, chunk) = Chunk::parse(input, encode_key, status_code)?;
+            version if SUPPORTED_VERSIONS.contains(&version) => {
+                // the types version follows the bytecode version
+                match input.first() {
+                    Some(&types_version) if !SUPPORTED_TYPES_VERSIONS.contains(&types_version) => {
+                        return Ok((
+                            input,
+                            Bytecode::Error(format!(
+                                "unsupported types version: {}",
+                                types_version
+                            )),
+                        ));
+                    }
+                    _ => {}
+                }
+                let (input, chunk) = Chunk::parse(input, encode_key, version)?;
                 Ok((input, Bytecode::Chunk(chunk)))
             }
-            _ => panic!("Unsupported bytecode version: {}", status_code),
+            version => Ok((
+                input,
+                Bytecode::Error(format!("unsupported bytecode version: {}", version)),
+            )),
         }
     }
 }
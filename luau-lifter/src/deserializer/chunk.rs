@@ -19,9 +19,6 @@ impl Chunk {
         } else {
             (input, 0)
         };
-        if types_version > 3 {
-            panic!("unsupported types version");
-        }
         let (input, string_table) = parse_list(input, parse_string)?;
         let input = if types_version == 3 {
             many_till(leb128_usize, char('\0'))(input)?.0
@@ -1,5 +1,6 @@
 use super::list::parse_list;
 use nom::{
+    error::{Error, ErrorKind},
     number::complete::{le_f32, le_f64, le_u32, le_u8},
     IResult,
 };
@@ -62,7 +63,7 @@ impl Constant {
                 let (input, w) = le_f32(input)?;
                 Ok((input, Constant::Vector(x, y, z, w)))
             }
-            _ => panic!("{}", tag),
+            _ => Err(nom::Err::Failure(Error::new(input, ErrorKind::Tag))),
         }
     }
 }
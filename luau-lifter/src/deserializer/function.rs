@@ -14,6 +14,14 @@ use super::{
 
 use crate::{instruction::*, op_code::OpCode};
 
+#[derive(Debug)]
+pub struct LocalVariable {
+    pub name: usize,
+    pub start_pc: usize,
+    pub end_pc: usize,
+    pub register: u8,
+}
+
 #[derive(Debug)]
 pub struct Function {
     pub max_stack_size: u8,
@@ -29,6 +37,7 @@ pub struct Function {
     pub line_gap_log2: Option<u8>,
     pub line_info_delta: Option<Vec<u8>>,
     pub abs_line_info_delta: Option<Vec<u32>>,
+    pub local_variables: Vec<LocalVariable>,
 }
 
 impl Function {
@@ -46,29 +55,7 @@ impl Function {
 
             // handle ops with aux values
             match op {
-                OpCode::LOP_GETGLOBAL
-                | OpCode::LOP_SETGLOBAL
-                | OpCode::LOP_GETIMPORT
-                | OpCode::LOP_GETTABLEKS
-                | OpCode::LOP_SETTABLEKS
-                | OpCode::LOP_NAMECALL
-                | OpCode::LOP_JUMPIFEQ
-                | OpCode::LOP_JUMPIFLE
-                | OpCode::LOP_JUMPIFLT
-                | OpCode::LOP_JUMPIFNOTEQ
-                | OpCode::LOP_JUMPIFNOTLE
-                | OpCode::LOP_JUMPIFNOTLT
-                | OpCode::LOP_NEWTABLE
-                | OpCode::LOP_SETLIST
-                | OpCode::LOP_FORGLOOP
-                | OpCode::LOP_LOADKX
-                | OpCode::LOP_FASTCALL2
-                | OpCode::LOP_FASTCALL2K
-                | OpCode::LOP_FASTCALL3
-                | OpCode::LOP_JUMPXEQKNIL
-                | OpCode::LOP_JUMPXEQKB
-                | OpCode::LOP_JUMPXEQKN
-                | OpCode::LOP_JUMPXEQKS => {
+                op if op.has_aux() => {
                     let aux = vec[pc + 1];
                     pc += 2;
                     match ins {
@@ -153,22 +140,29 @@ impl Function {
                 (input, Some(abs_line_info_delta))
             }
         };
-        let input = match le_u8(input)? {
-            (input, 0) => input,
+        let (input, local_variables) = match le_u8(input)? {
+            (input, 0) => (input, Vec::new()),
             (input, _) => {
-                panic!("we have debug info");
                 let (mut input, num_locvars) = leb128_usize(input)?;
+                let mut local_variables = Vec::with_capacity(num_locvars);
                 for _ in 0..num_locvars {
-                    (input, _) = leb128_usize(input)?;
-                    (input, _) = leb128_usize(input)?;
-                    (input, _) = leb128_usize(input)?;
-                    (input, _) = le_u8(input)?;
+                    let (name, start_pc, end_pc, register);
+                    (input, name) = leb128_usize(input)?;
+                    (input, start_pc) = leb128_usize(input)?;
+                    (input, end_pc) = leb128_usize(input)?;
+                    (input, register) = le_u8(input)?;
+                    local_variables.push(LocalVariable {
+                        name,
+                        start_pc,
+                        end_pc,
+                        register,
+                    });
                 }
                 let (mut input, num_upvalues) = leb128_usize(input)?;
                 for _ in 0..num_upvalues {
                     (input, _) = leb128_usize(input)?;
                 }
-                input
+                (input, local_variables)
             }
         };
         Ok((
@@ -186,6 +180,7 @@ impl Function {
                 line_gap_log2,
                 line_info_delta,
                 abs_line_info_delta,
+                local_variables,
             },
         ))
     }
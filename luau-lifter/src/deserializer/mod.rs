@@ -16,7 +16,12 @@ fn parse_string(input: &[u8]) -> IResult<&[u8], Vec<u8>> {
 pub fn deserialize(bytecode: &[u8], encode_key: u8) -> Result<bytecode::Bytecode, String> {
     match bytecode::Bytecode::parse(bytecode, encode_key) {
         Ok((_, deserialized_bytecode)) => Ok(deserialized_bytecode),
-        Err(err) => Err(err.to_string()),
+        Err(nom::Err::Error(err) | nom::Err::Failure(err)) => Err(format!(
+            "invalid bytecode at offset {}: {:?}",
+            bytecode.len() - err.input.len(),
+            err.code
+        )),
+        Err(nom::Err::Incomplete(_)) => Err("the bytecode ends unexpectedly".to_string()),
     }
 }
 
@@ -0,0 +1,189 @@
+use std::fmt::{self, Write};
+
+use crate::{
+    deserializer::{
+        self, bytecode::Bytecode, chunk::Chunk, constant::Constant, function::Function,
+    },
+    instruction::Instruction,
+    op_code::OpCode,
+};
+
+/// Disassembles `bytecode` into a human readable listing of every function.
+pub fn disassemble(bytecode: &[u8], encode_key: u8) -> Result<String, String> {
+    match deserializer::deserialize(bytecode, encode_key)? {
+        Bytecode::Error(msg) => Err(msg),
+        Bytecode::Chunk(chunk) => {
+            let mut output = String::new();
+            write_chunk(&mut output, &chunk).map_err(|e| e.to_string())?;
+            Ok(output)
+        }
+    }
+}
+
+fn string(chunk: &Chunk, index: usize) -> Option<String> {
+    // string indices are 1-based, 0 means no string
+    index
+        .checked_sub(1)
+        .and_then(|i| chunk.string_table.get(i))
+        .map(|s| String::from_utf8_lossy(s).into_owned())
+}
+
+fn write_chunk(output: &mut String, chunk: &Chunk) -> fmt::Result {
+    for (function_id, function) in chunk.functions.iter().enumerate() {
+        if function_id != 0 {
+            writeln!(output)?;
+        }
+        write_function(output, chunk, function_id, function)?;
+    }
+    Ok(())
+}
+
+fn write_function(
+    output: &mut String,
+    chunk: &Chunk,
+    function_id: usize,
+    function: &Function,
+) -> fmt::Result {
+    write!(output, "function {}", function_id)?;
+    if function_id == chunk.main {
+        write!(output, " (main)")?;
+    } else if let Some(name) = string(chunk, function.function_name) {
+        write!(output, " ({})", name)?;
+    }
+    writeln!(
+        output,
+        ": {} params{}, {} upvalues, {} stack slots",
+        function.num_parameters,
+        if function.is_vararg { " + ..." } else { "" },
+        function.num_upvalues,
+        function.max_stack_size,
+    )?;
+
+    if !function.constants.is_empty() {
+        writeln!(output, "constants:")?;
+        for (index, constant) in function.constants.iter().enumerate() {
+            write!(output, "  K{} = ", index)?;
+            write_constant(output, chunk, function, constant)?;
+            writeln!(output)?;
+        }
+    }
+
+    if !function.functions.is_empty() {
+        writeln!(
+            output,
+            "closures: {}",
+            function
+                .functions
+                .iter()
+                .map(|f| f.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )?;
+    }
+
+    writeln!(output, "code:")?;
+    let mut instructions = function.instructions.iter().enumerate();
+    while let Some((pc, instruction)) = instructions.next() {
+        write!(output, "  {:>4}  ", pc)?;
+        let has_aux = write_instruction(output, instruction)?;
+        writeln!(output)?;
+        if has_aux {
+            // the deserializer replaces aux words with a nop
+            instructions.next();
+        }
+    }
+    Ok(())
+}
+
+fn write_constant(
+    output: &mut String,
+    chunk: &Chunk,
+    function: &Function,
+    constant: &Constant,
+) -> fmt::Result {
+    match constant {
+        Constant::Nil => write!(output, "nil"),
+        Constant::Boolean(value) => write!(output, "{}", value),
+        Constant::Number(value) => write!(output, "{}", value),
+        &Constant::String(index) => match string(chunk, index) {
+            Some(string) => write!(output, "{:?}", string),
+            None => write!(output, "<invalid string {}>", index),
+        },
+        &Constant::Import(id) => {
+            let path = (0..(id >> 30) & 3)
+                .map(|i| {
+                    let index = (id >> (20 - i * 10)) & 1023;
+                    match function.constants.get(index) {
+                        Some(&Constant::String(index)) => {
+                            string(chunk, index).unwrap_or_else(|| "?".to_string())
+                        }
+                        _ => "?".to_string(),
+                    }
+                })
+                .collect::<Vec<_>>();
+            write!(output, "import {}", path.join("."))
+        }
+        Constant::Table(keys) => write!(output, "table ({} keys)", keys.len()),
+        Constant::Closure(function_id) => write!(output, "closure {}", function_id),
+        Constant::Vector(x, y, z, w) => write!(output, "vector({}, {}, {}, {})", x, y, z, w),
+    }
+}
+
+// returns whether the instruction has an aux word
+fn write_instruction(output: &mut String, instruction: &Instruction) -> Result<bool, fmt::Error> {
+    let name = |op_code: OpCode| {
+        format!("{:?}", op_code)
+            .trim_start_matches("LOP_")
+            .to_owned()
+    };
+    match *instruction {
+        Instruction::BC {
+            op_code,
+            a,
+            b,
+            c,
+            aux,
+        } => {
+            write!(output, "{:<16}{} {} {}", name(op_code), a, b, c)?;
+            if op_code.has_aux() {
+                write!(output, " [{:#x}]", aux)?;
+                return Ok(true);
+            }
+        }
+        Instruction::AD { op_code, a, d, aux } => {
+            write!(output, "{:<16}{} {}", name(op_code), a, d)?;
+            if op_code.has_aux() {
+                write!(output, " [{:#x}]", aux)?;
+                return Ok(true);
+            }
+        }
+        Instruction::E { op_code, e } => {
+            write!(output, "{:<16}{}", name(op_code), e)?;
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_versions() {
+        assert_eq!(
+            disassemble(&[9, 0], 1),
+            Err("unsupported bytecode version: 9".to_string())
+        );
+        assert_eq!(
+            disassemble(&[5, 9], 1),
+            Err("unsupported types version: 9".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_constant() {
+        // no strings, one function with a single NOP and a constant with tag 0xFF
+        let bytecode = [5, 0, 0, 1, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, 0xFF];
+        assert!(disassemble(&bytecode, 1).is_err());
+    }
+}
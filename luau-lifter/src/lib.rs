@@ -1,11 +1,14 @@
-mod deserializer;
+pub mod deserializer;
+mod disassembler;
 mod instruction;
 mod lifter;
 mod op_code;
+mod options;
 
 use ast::{
-    local_declarations::LocalDeclarer, name_locals::name_locals, replace_locals::replace_locals,
-    Traverse,
+    fold_compound_assignments, formatter::Formatter, interpolate_strings,
+    local_declarations::LocalDeclarer, methods::recover_methods, name_locals::name_locals,
+    replace_locals::replace_locals, Traverse,
 };
 
 use by_address::ByAddress;
@@ -20,52 +23,199 @@ use indexmap::IndexMap;
 
 use lifter::Lifter;
 
-//use cfg_ir::{dot, function::Function, ssa};
-use clap::Parser;
 use parking_lot::Mutex;
 use petgraph::algo::dominators::simple_fast;
+#[cfg(feature = "parallel")]
 use rayon::prelude::*;
 
 use anyhow::anyhow;
 use rustc_hash::FxHashMap;
 use triomphe::Arc;
-use walkdir::WalkDir;
 
 use std::{
-    fs::File,
-    io::{Read, Write},
-    path::Path,
-    time::Instant,
+    backtrace::Backtrace,
+    cell::{Cell, RefCell},
+    panic,
+    sync::Once,
 };
 
+pub use ast::{
+    formatter::{FormatOptions, IndentationMode, QuoteStyle, VectorStyle, DEFAULT_LINE_WIDTH},
+    Dialect,
+};
 use deserializer::bytecode::Bytecode;
+pub use disassembler::disassemble;
+pub use options::Options;
+
+/// A stage of the decompilation pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    Deserialize,
+    Lift,
+    Ssa,
+    Restructure,
+    Format,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [
+        Stage::Deserialize,
+        Stage::Lift,
+        Stage::Ssa,
+        Stage::Restructure,
+        Stage::Format,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Deserialize => "deserialize",
+            Stage::Lift => "lift",
+            Stage::Ssa => "ssa",
+            Stage::Restructure => "restructure",
+            Stage::Format => "format",
+        }
+    }
+}
+
+thread_local! {
+    // the stage the current thread is in, used to attribute panics
+    static STAGE: Cell<Stage> = const { Cell::new(Stage::Deserialize) };
+}
+
+fn enter_stage(stage: Stage) {
+    STAGE.with(|s| s.set(stage));
+}
+
+fn current_stage() -> Stage {
+    STAGE.with(|s| s.get())
+}
+
+/// Information about a single decompilation.
+#[derive(Debug, Default, Clone)]
+pub struct Statistics {
+    /// The number of functions in the chunk.
+    pub functions: usize,
+    /// The stage of every failure, one entry per function that failed to decompile.
+    pub failures: Vec<Stage>,
+    /// The number of unreachable blocks removed while structuring.
+    pub dead_blocks: usize,
+    /// The constructs in the output that can't be written in the chosen [`Dialect`].
+    pub unsupported: Vec<ast::Unsupported>,
+}
+
+thread_local! {
+    // the backtrace of the last panic on the current thread
+    static BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+    // whether panics on the current thread are caught by the decompiler, so they are recorded
+    // instead of printed
+    static SUPPRESS_PANICS: Cell<bool> = const { Cell::new(false) };
+}
+
+static PANIC_HOOK: Once = Once::new();
 
-#[cfg(feature = "dhat-heap")]
-#[global_allocator]
-static ALLOC: dhat::Alloc = dhat::Alloc;
-
-#[derive(Parser, Debug)]
-#[clap(about, version, author)]
-struct Args {
-    paths: Vec<String>,
-    /// Number of threads to use (0 = automatic)
-    #[clap(short, long, default_value_t = 0)]
-    threads: usize,
-    /// op = op * key % 256
-    /// For Roblox client bytecode, use 203
-    #[clap(short, long, default_value_t = 1)]
-    key: u8,
-    #[clap(short, long)]
-    recursive: bool,
-    #[clap(short, long)]
-    verbose: bool,
+// catches a panic in `f` without printing it. the panic hook is global, so it's installed once
+// and only records the backtrace on threads that are inside this function, other panics still
+// go to the previous hook
+fn catch_unwind_quietly<R>(f: impl FnOnce() -> R) -> std::thread::Result<R> {
+    PANIC_HOOK.call_once(|| {
+        let prev_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if SUPPRESS_PANICS.with(|s| s.get()) {
+                let trace = Backtrace::capture();
+                BACKTRACE.with(move |b| b.borrow_mut().replace(trace));
+            } else {
+                prev_hook(info);
+            }
+        }));
+    });
+    let suppressed = SUPPRESS_PANICS.with(|s| s.replace(true));
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(f));
+    SUPPRESS_PANICS.with(|s| s.set(suppressed));
+    result
+}
+
+// runs `f`, returning the stage it was in if it panicked
+fn catch_stage<R>(f: impl FnOnce() -> R) -> Result<R, Stage> {
+    catch_unwind_quietly(f).map_err(|_| current_stage())
+}
+
+type LiftedFunction = (Arc<Mutex<ast::Function>>, Function, Vec<ast::RcLocal>);
+
+/// The progress of a [`Decompiler`] after decompiling a single function.
+pub struct Progress {
+    /// The id of the function that was decompiled.
+    pub function_id: usize,
+    /// The debug name of the function, if it has one.
+    pub name: Option<String>,
+    /// The number of functions decompiled so far, including this one.
+    pub completed: usize,
+    /// The total number of functions to decompile.
+    pub total: usize,
+    /// The stage the function failed in, if it failed to decompile.
+    pub failure: Option<Stage>,
+    function: Arc<Mutex<ast::Function>>,
+    dialect: Dialect,
+    format_options: FormatOptions,
+    semantic_names: bool,
+}
+
+impl Progress {
+    /// Formats the decompiled function on its own.
+    ///
+    /// Locals are given provisional names and closures that have not been
+    /// decompiled yet are empty, the final output of the [`Decompiler`] may differ.
+    pub fn source(&self) -> String {
+        let closure = ast::Closure {
+            function: ByAddress(self.function.clone()),
+            upvalues: Vec::new(),
+        };
+        let mut body = ast::Block(vec![ast::Return::new(vec![closure.clone().into()]).into()]);
+        let unnamed = name_locals(&mut body, false, self.semantic_names);
+        let mut source = String::new();
+        Formatter::with_options(&mut source, self.dialect, self.format_options.clone())
+            .format_closure(&closure)
+            .unwrap();
+        // the locals are shared with the decompiler, which names them once every function is done
+        for local in unnamed {
+            local.0 .0.lock().0 = None;
+        }
+        source
+    }
 }
 
-pub fn decompile_bytecode(bytecode: &[u8], encode_key: u8) -> String {
-    let chunk = deserializer::deserialize(bytecode, encode_key).unwrap();
-    match chunk {
-        Bytecode::Error(msg) => msg,
-        Bytecode::Chunk(chunk) => {
+/// Decompiles a chunk one function at a time.
+pub struct Decompiler {
+    main: Arc<Mutex<ast::Function>>,
+    pending: std::vec::IntoIter<LiftedFunction>,
+    total: usize,
+    upvalues: FxHashMap<ByAddress<Arc<Mutex<ast::Function>>>, Vec<ast::RcLocal>>,
+    dialect: Dialect,
+    format_options: FormatOptions,
+    semantic_names: bool,
+    emit_dead_code: bool,
+    statistics: Statistics,
+}
+
+impl Decompiler {
+    /// Deserializes and lifts `bytecode`.
+    ///
+    /// If the chunk can't be decompiled, the output to show in place of a
+    /// decompilation is returned instead.
+    pub fn new(bytecode: &[u8], encode_key: u8) -> Result<Self, (String, Statistics)> {
+        let mut statistics = Statistics::default();
+        enter_stage(Stage::Deserialize);
+        let chunk = match catch_stage(|| deserializer::deserialize(bytecode, encode_key)) {
+            Ok(Ok(Bytecode::Chunk(chunk))) => chunk,
+            Ok(Ok(Bytecode::Error(msg))) => return Err((msg, statistics)),
+            Ok(Err(_)) | Err(_) => {
+                statistics.failures.push(Stage::Deserialize);
+                return Err(("-- failed to deserialize bytecode".to_string(), statistics));
+            }
+        };
+        statistics.functions = chunk.functions.len();
+
+        enter_stage(Stage::Lift);
+        let lifted = catch_stage(|| {
             let mut lifted = Vec::new();
             let mut stack = vec![(Arc::<Mutex<ast::Function>>::default(), chunk.main)];
             while let Some((ast_func, func_id)) = stack.pop() {
@@ -74,71 +224,196 @@ pub fn decompile_bytecode(bytecode: &[u8], encode_key: u8) -> String {
                 lifted.push((ast_func, function, upvalues));
                 stack.extend(child_functions.into_iter().map(|(a, f)| (a.0, f)));
             }
+            lifted
+        });
+        let lifted = match lifted {
+            Ok(lifted) => lifted,
+            Err(stage) => {
+                statistics.failures.push(stage);
+                return Err(("-- failed to lift bytecode".to_string(), statistics));
+            }
+        };
 
-            let (main, ..) = lifted.first().unwrap().clone();
-            let mut upvalues = lifted
-                .into_iter()
-                .map(|(ast_function, function, upvalues_in)| {
-                    use std::{backtrace::Backtrace, cell::RefCell, fmt::Write, panic};
+        let (main, ..) = lifted.first().unwrap().clone();
+        Ok(Self {
+            main,
+            total: lifted.len(),
+            pending: lifted.into_iter(),
+            upvalues: FxHashMap::default(),
+            dialect: Dialect::Luau,
+            format_options: FormatOptions::default(),
+            semantic_names: false,
+            emit_dead_code: false,
+            statistics,
+        })
+    }
 
-                    thread_local! {
-                        static BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
-                    }
+    /// Sets the dialect to decompile to, Luau by default.
+    ///
+    /// Must be set before any function is decompiled.
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Sets how the output is formatted, how locals are named and whether dead code is kept.
+    pub fn with_options(mut self, options: &Options) -> Self {
+        self.format_options = options.format_options();
+        self.semantic_names = options.semantic_names;
+        self.emit_dead_code = options.emit_dead_code;
+        self
+    }
+
+    /// The total number of functions to decompile.
+    pub fn total(&self) -> usize {
+        self.total
+    }
 
-                    let function_id = function.id;
-                    let mut args = std::panic::AssertUnwindSafe(Some((
-                        ast_function.clone(),
+    /// Decompiles the next function, returns `None` once every function has been decompiled.
+    pub fn step(&mut self) -> Option<Progress> {
+        let (ast_function, function, upvalues_in) = self.pending.next()?;
+        let function_id = function.id;
+        let name = ast_function.lock().name.clone();
+        let (dialect, emit_dead_code) = (self.dialect, self.emit_dead_code);
+        let (ast_function, upvalues, dead_blocks, failure) =
+            catch_decompile_function(ast_function, function, upvalues_in, dialect, emit_dead_code);
+        self.statistics.dead_blocks += dead_blocks;
+        self.statistics.failures.extend(failure);
+        let function = ast_function.0.clone();
+        self.upvalues.insert(ast_function, upvalues);
+        Some(Progress {
+            function_id,
+            name,
+            completed: self.total - self.pending.len(),
+            total: self.total,
+            failure,
+            function,
+            dialect,
+            format_options: self.format_options.clone(),
+            semantic_names: self.semantic_names,
+        })
+    }
+
+    /// Decompiles any remaining functions and formats the chunk.
+    pub fn finish(mut self) -> (String, Statistics) {
+        #[cfg(feature = "parallel")]
+        {
+            let pending = self.pending.by_ref().collect::<Vec<_>>();
+            let (dialect, emit_dead_code) = (self.dialect, self.emit_dead_code);
+            let results = pending
+                .into_par_iter()
+                .map(|(ast_function, function, upvalues_in)| {
+                    catch_decompile_function(
+                        ast_function,
                         function,
                         upvalues_in,
-                    )));
-
-                    let prev_hook = panic::take_hook();
-                    panic::set_hook(Box::new(|_| {
-                        let trace = Backtrace::capture();
-                        BACKTRACE.with(move |b| b.borrow_mut().replace(trace));
-                    }));
-                    let result = panic::catch_unwind(move || {
-                        let (ast_function, function, upvalues_in) = args.take().unwrap();
-                        decompile_function(ast_function, function, upvalues_in)
-                    });
-                    panic::set_hook(prev_hook);
-
-                    match result {
-                        Ok(r) => r,
-                        Err(e) => {
-                            let panic_information = match e.downcast::<String>() {
-                                Ok(v) => *v,
-                                Err(e) => match e.downcast::<&str>() {
-                                    Ok(v) => v.to_string(),
-                                    _ => "Unknown Source of Error".to_owned(),
-                                },
-                            };
-
-                            let mut message = String::new();
-                            writeln!(message, "failed to decompile").unwrap();
-                            // writeln!(message, "function {} panicked at '{}'", function_id, panic_information).unwrap();
-                            // if let Some(backtrace) = BACKTRACE.with(|b| b.borrow_mut().take()) {
-                            //     write!(message, "stack backtrace:\n{}", backtrace).unwrap();
-                            // }
-
-                            ast_function.lock().body.extend(
-                                message
-                                    .trim_end()
-                                    .split('\n')
-                                    .map(|s| ast::Comment::new(s.to_string()).into()),
-                            );
-                            (ByAddress(ast_function), Vec::new())
-                        }
-                    }
+                        dialect,
+                        emit_dead_code,
+                    )
                 })
-                .collect::<FxHashMap<_, _>>();
-
-            let main = ByAddress(main);
-            upvalues.remove(&main);
-            let mut body = Arc::try_unwrap(main.0).unwrap().into_inner().body;
-            link_upvalues(&mut body, &mut upvalues);
-            name_locals(&mut body, true);
-            body.to_string()
+                .collect::<Vec<_>>();
+            for (ast_function, upvalues, dead_blocks, failure) in results {
+                self.statistics.dead_blocks += dead_blocks;
+                self.statistics.failures.extend(failure);
+                self.upvalues.insert(ast_function, upvalues);
+            }
+        }
+        // without `parallel`, or if the functions were already decompiled, this is sequential
+        while self.step().is_some() {}
+
+        let main = ByAddress(self.main);
+        self.upvalues.remove(&main);
+        let mut body = Arc::try_unwrap(main.0).unwrap().into_inner().body;
+        link_upvalues(&mut body, &mut self.upvalues);
+        recover_methods(&mut body);
+        name_locals(&mut body, true, self.semantic_names);
+        self.statistics.unsupported = self.dialect.validate(&body);
+
+        enter_stage(Stage::Format);
+        match catch_stage(|| {
+            let mut source = String::new();
+            Formatter::format(&body, &mut source, self.dialect, self.format_options).unwrap();
+            source
+        }) {
+            Ok(source) => (source, self.statistics),
+            Err(stage) => {
+                self.statistics.failures.push(stage);
+                (
+                    "-- failed to format decompiled code".to_string(),
+                    self.statistics,
+                )
+            }
+        }
+    }
+}
+
+pub fn decompile_bytecode(bytecode: &[u8], encode_key: u8, options: &Options) -> String {
+    decompile_bytecode_with_statistics(bytecode, encode_key, options).0
+}
+
+pub fn decompile_bytecode_with_statistics(
+    bytecode: &[u8],
+    encode_key: u8,
+    options: &Options,
+) -> (String, Statistics) {
+    match Decompiler::new(bytecode, encode_key) {
+        Ok(decompiler) => decompiler.with_options(options).finish(),
+        Err(res) => res,
+    }
+}
+
+// decompiles a function, replacing its body with an error comment if it panics
+fn catch_decompile_function(
+    ast_function: Arc<Mutex<ast::Function>>,
+    function: Function,
+    upvalues_in: Vec<ast::RcLocal>,
+    dialect: Dialect,
+    emit_dead_code: bool,
+) -> (
+    ByAddress<Arc<Mutex<ast::Function>>>,
+    Vec<ast::RcLocal>,
+    usize,
+    Option<Stage>,
+) {
+    use std::fmt::Write;
+
+    let function_id = function.id;
+    let result = catch_unwind_quietly(|| {
+        decompile_function(
+            ast_function.clone(),
+            function,
+            upvalues_in,
+            dialect,
+            emit_dead_code,
+        )
+    });
+
+    match result {
+        Ok((ast_function, upvalues, dead_blocks)) => (ast_function, upvalues, dead_blocks, None),
+        Err(e) => {
+            let stage = current_stage();
+            let panic_information = match e.downcast::<String>() {
+                Ok(v) => *v,
+                Err(e) => match e.downcast::<&str>() {
+                    Ok(v) => v.to_string(),
+                    _ => "Unknown Source of Error".to_owned(),
+                },
+            };
+
+            let mut message = String::new();
+            writeln!(message, "failed to decompile").unwrap();
+            // writeln!(message, "function {} panicked at '{}'", function_id, panic_information).unwrap();
+            // if let Some(backtrace) = BACKTRACE.with(|b| b.borrow_mut().take()) {
+            //     write!(message, "stack backtrace:\n{}", backtrace).unwrap();
+            // }
+
+            ast_function.lock().body.extend(
+                message
+                    .trim_end()
+                    .split('\n')
+                    .map(|s| ast::Comment::new(s.to_string()).into()),
+            );
+            (ByAddress(ast_function), Vec::new(), 0, Some(stage))
         }
     }
 }
@@ -147,7 +422,19 @@ fn decompile_function(
     ast_function: Arc<Mutex<ast::Function>>,
     mut function: Function,
     upvalues_in: Vec<ast::RcLocal>,
-) -> (ByAddress<Arc<Mutex<ast::Function>>>, Vec<ast::RcLocal>) {
+    dialect: Dialect,
+    emit_dead_code: bool,
+) -> (
+    ByAddress<Arc<Mutex<ast::Function>>>,
+    Vec<ast::RcLocal>,
+    usize,
+) {
+    enter_stage(Stage::Ssa);
+    // ssa construction replaces the parameters with unnamed locals
+    let self_parameter = function
+        .parameters
+        .first()
+        .is_some_and(|p| p.0 .0.lock().0.as_deref() == Some("self"));
     let (local_count, local_groups, upvalue_in_groups, upvalue_passed_groups) =
         cfg::ssa::construct(&mut function, &upvalues_in);
     let upvalue_to_group = upvalue_in_groups
@@ -181,7 +468,7 @@ fn decompile_function(
 
         ssa::inline::inline(&mut function, &local_to_group, &upvalue_to_group);
 
-        if structure_conditionals(&mut function)
+        if structure_conditionals(&mut function, dialect.supports_if_expression())
         // || {
         //     let post_dominators = post_dominators(function.graph_mut());
         //     structure_for_loops(&mut function, &dominators, &post_dominators)
@@ -207,9 +494,26 @@ fn decompile_function(
     )
     .destruct();
 
+    enter_stage(Stage::Restructure);
     let params = std::mem::take(&mut function.parameters);
+    if self_parameter {
+        params[0].0 .0.lock().0 = Some("self".to_string());
+    }
     let is_variadic = function.is_variadic;
-    let block = Arc::new(restructure::lift(function).into());
+    // neither dialect has goto
+    let (mut block, restructure_statistics) = restructure::lift_with_options(
+        function,
+        restructure::Options {
+            eliminate_gotos: true,
+            emit_dead_code,
+            allow_continue: dialect.supports_continue(),
+            ..Default::default()
+        },
+    );
+    if dialect.supports_interpolated_string() {
+        interpolate_strings(&mut block);
+    }
+    let block = Arc::new(block.into());
     LocalDeclarer::default().declare_locals(
         // TODO: why does block.clone() not work?
         Arc::clone(&block),
@@ -219,10 +523,17 @@ fn decompile_function(
     {
         let mut ast_function = ast_function.lock();
         ast_function.body = Arc::try_unwrap(block).unwrap().into_inner();
+        if dialect.supports_compound_assign() {
+            fold_compound_assignments(&mut ast_function.body);
+        }
         ast_function.parameters = params;
         ast_function.is_variadic = is_variadic;
     }
-    (ByAddress(ast_function), upvalues_in)
+    (
+        ByAddress(ast_function),
+        upvalues_in,
+        restructure_statistics.dead_blocks,
+    )
 }
 
 fn link_upvalues(
@@ -252,7 +563,7 @@ fn link_upvalues(
                 replace_locals(&mut function.body, &local_map);
             }
         });
-        match stat {
+        match stat.inner_mut() {
             ast::Statement::If(r#if) => {
                 link_upvalues(&mut r#if.then_block.lock(), upvalues);
                 link_upvalues(&mut r#if.else_block.lock(), upvalues);
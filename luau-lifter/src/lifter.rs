@@ -95,8 +95,16 @@ impl<'a> Lifter<'a> {
             self.upvalues.push(ast::RcLocal::default());
         }
 
+        // the first parameter of a method is named `self` in the debug info, if it's present
+        let is_method = self.function_list[self.function.id]
+            .local_variables
+            .iter()
+            .filter(|l| l.register == 0)
+            .min_by_key(|l| l.start_pc)
+            .is_some_and(|l| l.name != 0 && self.string_table[l.name - 1] == b"self");
         for i in 0..self.function_list[self.function.id].num_parameters {
-            let parameter = ast::RcLocal::default();
+            let name = (i == 0 && is_method).then(|| "self".to_string());
+            let parameter = ast::RcLocal::new(ast::Local::new(name));
             self.function.parameters.push(parameter.clone());
             self.register_map.insert(i as usize, parameter);
         }
@@ -1319,7 +1327,7 @@ impl<'a> Lifter<'a> {
                 // TODO: what does the official deserializer do if v == 0?
                 ast::Literal::String(self.string_table[*v - 1].clone())
             }
-            BytecodeConstant::Vector(x, y, z, _) => ast::Literal::Vector(*x, *y, *z),
+            BytecodeConstant::Vector(x, y, z, w) => ast::Literal::Vector(*x, *y, *z, *w),
             _ => unimplemented!(),
         };
         self.constant_map
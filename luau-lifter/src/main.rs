@@ -1,10 +1,48 @@
+use std::{fs, path::PathBuf, time::Instant};
+
+use clap::Parser;
+use luau_lifter::Options;
+
+#[cfg(feature = "dhat-heap")]
+#[global_allocator]
+static ALLOC: dhat::Alloc = dhat::Alloc;
+
+#[derive(Parser, Debug)]
+#[clap(about, version, author)]
+struct Args {
+    /// The bytecode file to decompile
+    file: PathBuf,
+    /// The opcodes are encoded with op = op * 203 % 256, like Roblox client bytecode
+    #[clap(short = 'e', long = "encoded")]
+    encoded: bool,
+    #[clap(short, long)]
+    verbose: bool,
+    #[clap(flatten)]
+    options: Options,
+}
+
 fn main() {
-    let file_name = std::env::args().nth(1).expect("expected exactly one file");
-    let key = std::env::args()
-        .nth(2)
-        .or_else(|| None)
-        .map(|s| if s == "-e" { 203 } else { panic!() })
-        .unwrap_or(1);
-    let bytecode = std::fs::read(file_name).expect("failed to read file");
-    println!("{}", luau_lifter::decompile_bytecode(&bytecode, key));
+    #[cfg(feature = "dhat-heap")]
+    let _profiler = dhat::Profiler::new_heap();
+
+    let args = Args::parse();
+    let key = if args.encoded { 203 } else { 1 };
+
+    let bytecode = fs::read(&args.file).expect("failed to read file");
+    let now = Instant::now();
+    let (source, statistics) =
+        luau_lifter::decompile_bytecode_with_statistics(&bytecode, key, &args.options);
+    if args.verbose {
+        eprintln!(
+            "decompiled {} functions in {:?}, {} failed, {} dead blocks removed",
+            statistics.functions,
+            now.elapsed(),
+            statistics.failures.len(),
+            statistics.dead_blocks
+        );
+        for unsupported in &statistics.unsupported {
+            eprintln!("{}", unsupported);
+        }
+    }
+    println!("{}", source);
 }
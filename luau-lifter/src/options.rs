@@ -0,0 +1,94 @@
+use ast::formatter::{FormatOptions, IndentationMode, QuoteStyle, VectorStyle, DEFAULT_LINE_WIDTH};
+
+/// How a chunk is decompiled, shared by the command line and the servers.
+///
+/// With the `serde` feature, it can be deserialized from snake_case or camelCase keys
+/// and every key is optional.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::Args))]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+pub struct Options {
+    /// Indent with this many spaces instead of tabs
+    #[cfg_attr(feature = "cli", arg(long = "spaces"))]
+    #[cfg_attr(feature = "serde", serde(alias = "indentSpaces"))]
+    pub indent_spaces: Option<u8>,
+    /// Prefer single quotes for strings
+    #[cfg_attr(feature = "cli", arg(long))]
+    #[cfg_attr(feature = "serde", serde(alias = "singleQuotes"))]
+    pub single_quotes: bool,
+    /// Write a separator after the last field of tables that span multiple lines
+    #[cfg_attr(feature = "cli", arg(long))]
+    #[cfg_attr(feature = "serde", serde(alias = "trailingSeparators"))]
+    pub trailing_separators: bool,
+    /// End every statement with a semicolon
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub semicolons: bool,
+    /// Write integers that look like bit masks in hexadecimal when they are used with `bit32`
+    #[cfg_attr(feature = "cli", arg(long))]
+    #[cfg_attr(feature = "serde", serde(alias = "hexIntegers"))]
+    pub hex_integers: bool,
+    /// Escape non-printable characters with `\u{...}`
+    #[cfg_attr(feature = "cli", arg(long))]
+    #[cfg_attr(feature = "serde", serde(alias = "unicodeEscapes"))]
+    pub unicode_escapes: bool,
+    /// Write vectors with `vector.create` instead of `Vector3.new`
+    #[cfg_attr(feature = "cli", arg(long))]
+    #[cfg_attr(feature = "serde", serde(alias = "vectorLibrary"))]
+    pub vector_library: bool,
+    /// Name locals after the values they hold, like `Players` for `game:GetService("Players")`
+    #[cfg_attr(feature = "cli", arg(long))]
+    #[cfg_attr(feature = "serde", serde(alias = "semanticNames"))]
+    pub semantic_names: bool,
+    /// Keep blocks that can never be executed in a `--[[ dead code ]]` comment
+    #[cfg_attr(feature = "cli", arg(long))]
+    #[cfg_attr(feature = "serde", serde(alias = "emitDeadCode"))]
+    pub emit_dead_code: bool,
+    /// The width lines are broken at where possible
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = DEFAULT_LINE_WIDTH))]
+    #[cfg_attr(feature = "serde", serde(alias = "lineWidth"))]
+    pub line_width: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            indent_spaces: None,
+            single_quotes: false,
+            trailing_separators: false,
+            semicolons: false,
+            hex_integers: false,
+            unicode_escapes: false,
+            vector_library: false,
+            semantic_names: false,
+            emit_dead_code: false,
+            line_width: DEFAULT_LINE_WIDTH,
+        }
+    }
+}
+
+impl Options {
+    /// The options the formatter lays the output out with.
+    pub fn format_options(&self) -> FormatOptions {
+        FormatOptions {
+            indentation_mode: match self.indent_spaces {
+                Some(spaces) => IndentationMode::Spaces(spaces),
+                None => IndentationMode::Tab,
+            },
+            quote_style: if self.single_quotes {
+                QuoteStyle::Single
+            } else {
+                QuoteStyle::Double
+            },
+            trailing_separators: self.trailing_separators,
+            semicolons: self.semicolons,
+            hex_integers: self.hex_integers,
+            unicode_escapes: self.unicode_escapes,
+            vector_style: if self.vector_library {
+                VectorStyle::Library
+            } else {
+                VectorStyle::Roblox
+            },
+            line_width: self.line_width,
+        }
+    }
+}
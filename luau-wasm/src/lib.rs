@@ -0,0 +1,65 @@
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+
+/// op = op * key % 256
+/// For Roblox client bytecode, use 203
+const DEFAULT_ENCODE_KEY: u8 = 203;
+
+fn default_encode_key() -> u8 {
+    DEFAULT_ENCODE_KEY
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Options {
+    #[serde(default = "default_encode_key")]
+    encode_key: u8,
+    #[serde(flatten)]
+    decompile: luau_lifter::Options,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            encode_key: DEFAULT_ENCODE_KEY,
+            decompile: Default::default(),
+        }
+    }
+}
+
+fn parse_options(options: JsValue) -> Result<Options, JsError> {
+    if options.is_undefined() || options.is_null() {
+        return Ok(Options::default());
+    }
+    serde_wasm_bindgen::from_value(options).map_err(|err| JsError::new(&err.to_string()))
+}
+
+#[wasm_bindgen(start)]
+fn start() {
+    console_error_panic_hook::set_once();
+}
+
+/// Decompiles Luau bytecode.
+///
+/// `options` is an optional object, `{ encodeKey: number, indentSpaces: number,
+/// singleQuotes: boolean, trailingSeparators: boolean, semicolons: boolean,
+/// hexIntegers: boolean, unicodeEscapes: boolean, vectorLibrary: boolean, semanticNames: boolean,
+/// emitDeadCode: boolean, lineWidth: number }`, every field is optional.
+#[wasm_bindgen]
+pub fn decompile(bytecode: &[u8], options: JsValue) -> Result<String, JsError> {
+    let options = parse_options(options)?;
+    Ok(luau_lifter::decompile_bytecode(
+        bytecode,
+        options.encode_key,
+        &options.decompile,
+    ))
+}
+
+/// Disassembles Luau bytecode into a listing of every function.
+///
+/// `options` is an optional object, `{ encodeKey: number }`.
+#[wasm_bindgen]
+pub fn disassemble(bytecode: &[u8], options: JsValue) -> Result<String, JsError> {
+    let options = parse_options(options)?;
+    luau_lifter::disassemble(bytecode, options.encode_key).map_err(|err| JsError::new(&err))
+}
@@ -1,23 +1,237 @@
 use futures_util::StreamExt;
 extern crate console_error_panic_hook;
 
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+    time::Duration,
+};
+
 use base64::prelude::*;
-use luau_lifter::decompile_bytecode;
+use luau_lifter::{decompile_bytecode, Decompiler, Options};
 use serde::{Deserialize, Serialize};
 use worker::*;
 
-const AUTH_SECRET: &str = "ymjKH2O3BbO3bDSsKmpo3ek3vHxIWYLQfj0";
+/// Name of the secret binding holding the expected `Authorization` header.
+/// Set it with `wrangler secret put AUTH_SECRET`.
+const AUTH_SECRET_BINDING: &str = "AUTH_SECRET";
+/// op = op * key % 256
+/// For Roblox client bytecode, use 203
+const DEFAULT_ENCODE_KEY: u8 = 203;
+
+fn default_encode_key() -> u8 {
+    DEFAULT_ENCODE_KEY
+}
+
+// sets the option named `key` from a query parameter of `/decompile`, returns false if `value` is
+// invalid. unknown keys are ignored
+fn set_option(options: &mut Options, key: &str, value: &str) -> bool {
+    fn parse<T: std::str::FromStr>(value: &str, field: &mut T) -> bool {
+        value.parse().map(|value| *field = value).is_ok()
+    }
+
+    match key {
+        "indent_spaces" => value
+            .parse()
+            .map(|spaces| options.indent_spaces = Some(spaces))
+            .is_ok(),
+        "single_quotes" => parse(value, &mut options.single_quotes),
+        "trailing_separators" => parse(value, &mut options.trailing_separators),
+        "semicolons" => parse(value, &mut options.semicolons),
+        "hex_integers" => parse(value, &mut options.hex_integers),
+        "unicode_escapes" => parse(value, &mut options.unicode_escapes),
+        "vector_library" => parse(value, &mut options.vector_library),
+        "semantic_names" => parse(value, &mut options.semantic_names),
+        "emit_dead_code" => parse(value, &mut options.emit_dead_code),
+        "line_width" => parse(value, &mut options.line_width),
+        _ => true,
+    }
+}
 
 #[derive(Deserialize)]
 struct DecompileMessage {
     id: String,
     encoded_bytecode: String,
+    #[serde(default = "default_encode_key")]
+    encode_key: u8,
+    /// Send the source of every function as soon as it's decompiled
+    #[serde(default)]
+    stream_functions: bool,
+    #[serde(flatten)]
+    options: Options,
 }
 
-#[derive(Serialize)]
-struct DecompileResponse {
+#[derive(Deserialize)]
+struct CancelMessage {
     id: String,
-    decompilation: String,
+}
+
+enum ClientMessage {
+    Decompile(DecompileMessage),
+    Cancel(CancelMessage),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    /// A function has been decompiled
+    Progress {
+        id: String,
+        function_id: usize,
+        completed: usize,
+        total: usize,
+    },
+    /// The source of a single function, only sent if `stream_functions` was set
+    Function {
+        id: String,
+        function_id: usize,
+        name: Option<String>,
+        source: String,
+    },
+    /// The decompilation of the whole chunk
+    #[serde(rename = "result")]
+    Decompilation {
+        id: String,
+        decompilation: String,
+    },
+    Cancelled {
+        id: String,
+    },
+    Error {
+        // `None` if the message was too malformed to read an id from
+        id: Option<String>,
+        error: String,
+    },
+}
+
+impl ServerMessage {
+    fn error(id: Option<String>, error: impl ToString) -> Self {
+        Self::Error {
+            id,
+            error: error.to_string(),
+        }
+    }
+}
+
+// the cancellation flags of the decompilations running on a websocket
+type Running = Rc<RefCell<HashMap<String, Rc<Cell<bool>>>>>;
+
+// returns a response to send back if the request is not authorized
+fn check_authorization(req: &Request, ctx: &RouteContext<()>) -> Result<Option<Response>> {
+    let secret = ctx.secret(AUTH_SECRET_BINDING)?.to_string();
+    match req.headers().get("Authorization")? {
+        None => Response::error("authorization header is required", 401).map(Some),
+        Some(license) if license != secret => Response::error("invalid license", 403).map(Some),
+        Some(_) => Ok(None),
+    }
+}
+
+fn parse_message(text: &str) -> std::result::Result<ClientMessage, ServerMessage> {
+    let value = serde_json::from_str::<serde_json::Value>(text)
+        .map_err(|err| ServerMessage::error(None, format!("malformed message: {err}")))?;
+    let id = value
+        .get("id")
+        .and_then(|id| id.as_str())
+        .map(|id| id.to_owned());
+    let malformed = |err| ServerMessage::error(id.clone(), format!("malformed message: {err}"));
+    // messages without a type are decompile requests
+    match value.get("type").and_then(|t| t.as_str()) {
+        None | Some("decompile") => serde_json::from_value(value)
+            .map(ClientMessage::Decompile)
+            .map_err(malformed),
+        Some("cancel") => serde_json::from_value(value)
+            .map(ClientMessage::Cancel)
+            .map_err(malformed),
+        Some(other) => Err(ServerMessage::error(
+            id.clone(),
+            format!("unknown message type: {other}"),
+        )),
+    }
+}
+
+fn send(server: &WebSocket, msg: &ServerMessage) {
+    if let Err(err) = server.send(msg) {
+        console_error!("failed to send websocket message: {}", err);
+    }
+}
+
+async fn decompile_ws(server: WebSocket, running: Running, msg: DecompileMessage) {
+    let bytecode = match BASE64_STANDARD.decode(&msg.encoded_bytecode) {
+        Ok(bytecode) => bytecode,
+        Err(err) => {
+            return send(
+                &server,
+                &ServerMessage::error(
+                    Some(msg.id),
+                    format!("bytecode must be base64 encoded: {err}"),
+                ),
+            )
+        }
+    };
+    let mut decompiler = match Decompiler::new(&bytecode, msg.encode_key) {
+        Ok(decompiler) => decompiler.with_options(&msg.options),
+        Err((error, _)) => return send(&server, &ServerMessage::error(Some(msg.id), error)),
+    };
+
+    let cancelled = Rc::new(Cell::new(false));
+    // a new request with the same id supersedes the old one
+    if let Some(previous) = running
+        .borrow_mut()
+        .insert(msg.id.clone(), cancelled.clone())
+    {
+        previous.set(true);
+    }
+
+    while let Some(progress) = decompiler.step() {
+        send(
+            &server,
+            &ServerMessage::Progress {
+                id: msg.id.clone(),
+                function_id: progress.function_id,
+                completed: progress.completed,
+                total: progress.total,
+            },
+        );
+        if msg.stream_functions {
+            send(
+                &server,
+                &ServerMessage::Function {
+                    id: msg.id.clone(),
+                    function_id: progress.function_id,
+                    source: progress.source(),
+                    name: progress.name,
+                },
+            );
+        }
+
+        // give the event loop a chance to deliver cancel messages
+        Delay::from(Duration::ZERO).await;
+        if cancelled.get() {
+            break;
+        }
+    }
+
+    // a newer decompilation with the same id may have replaced it and finished already
+    if running
+        .borrow()
+        .get(&msg.id)
+        .is_some_and(|c| Rc::ptr_eq(c, &cancelled))
+    {
+        running.borrow_mut().remove(&msg.id);
+    }
+    if cancelled.get() {
+        send(&server, &ServerMessage::Cancelled { id: msg.id });
+    } else {
+        let (decompilation, _) = decompiler.finish();
+        send(
+            &server,
+            &ServerMessage::Decompilation {
+                id: msg.id,
+                decompilation,
+            },
+        );
+    }
 }
 
 #[event(fetch, respond_with_errors)]
@@ -26,15 +240,9 @@ pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> Result<Respo
 
     let router = Router::new();
     router
-        .get_async("/decompile_ws", |req, _ctx| async move {
-            let license = req
-                .headers()
-                .get("Authorization")
-                .unwrap_or_default()
-                .expect("authorization header is required");
-
-            if license != AUTH_SECRET {
-                return Response::error("invalid license", 403);
+        .get_async("/decompile_ws", |req, ctx| async move {
+            if let Some(response) = check_authorization(&req, &ctx)? {
+                return Ok(response);
             }
 
             let pair = WebSocketPair::new()?;
@@ -42,44 +250,73 @@ pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> Result<Respo
             server.accept()?;
 
             wasm_bindgen_futures::spawn_local(async move {
-                let mut event_stream = server.events().expect("could not open stream");
+                let mut event_stream = match server.events() {
+                    Ok(event_stream) => event_stream,
+                    Err(err) => {
+                        console_error!("could not open websocket stream: {}", err);
+                        return;
+                    }
+                };
+                let running = Running::default();
                 while let Some(event) = event_stream.next().await {
-                    if let WebsocketEvent::Message(msg) =
-                        event.expect("received error in websocket")
-                    {
-                        let msg = msg
-                            .json::<DecompileMessage>()
-                            .expect("malformed decompile message");
-                        let bytecode = BASE64_STANDARD
-                            .decode(msg.encoded_bytecode)
-                            .expect("bytecode must be base64 encoded");
-                        let resp = DecompileResponse {
-                            id: msg.id,
-                            decompilation: decompile_bytecode(&bytecode, 1),
-                        };
-                        server
-                            .send_with_str(serde_json::to_string(&resp).unwrap())
-                            .unwrap();
+                    let msg = match event {
+                        Ok(WebsocketEvent::Message(msg)) => msg,
+                        Ok(WebsocketEvent::Close(_)) => break,
+                        Err(err) => {
+                            console_error!("received error in websocket: {}", err);
+                            break;
+                        }
+                    };
+                    let msg = match msg.text() {
+                        Some(text) => parse_message(&text),
+                        None => Err(ServerMessage::error(None, "messages must be text")),
+                    };
+                    match msg {
+                        Ok(ClientMessage::Decompile(msg)) => {
+                            wasm_bindgen_futures::spawn_local(decompile_ws(
+                                server.clone(),
+                                running.clone(),
+                                msg,
+                            ));
+                        }
+                        Ok(ClientMessage::Cancel(msg)) => match running.borrow().get(&msg.id) {
+                            Some(cancelled) => cancelled.set(true),
+                            None => send(
+                                &server,
+                                &ServerMessage::error(
+                                    Some(msg.id),
+                                    "no decompilation with this id is running",
+                                ),
+                            ),
+                        },
+                        Err(msg) => send(&server, &msg),
                     }
                 }
             });
 
             Response::from_websocket(pair.client)
         })
-        .post_async("/decompile", |mut req, _ctx| async move {
-            let license = req
-                .headers()
-                .get("Authorization")
-                .unwrap_or_default()
-                .expect("authorization header is required");
-
-            if license != AUTH_SECRET {
-                return Response::error("invalid license", 403);
+        .post_async("/decompile", |mut req, ctx| async move {
+            if let Some(response) = check_authorization(&req, &ctx)? {
+                return Ok(response);
+            }
+
+            let mut encode_key = DEFAULT_ENCODE_KEY;
+            let mut options = Options::default();
+            for (key, value) in req.url()?.query_pairs() {
+                if key == "encode_key" {
+                    match value.parse::<u8>() {
+                        Ok(value) => encode_key = value,
+                        Err(_) => return Response::error("invalid encode key", 400),
+                    }
+                } else if !set_option(&mut options, &key, &value) {
+                    return Response::error(format!("invalid {key}"), 400);
+                }
             }
 
             let encoded_bytecode = req.bytes().await?;
             match BASE64_STANDARD.decode(encoded_bytecode) {
-                Ok(bytecode) => Response::ok(decompile_bytecode(&bytecode, 203)),
+                Ok(bytecode) => Response::ok(decompile_bytecode(&bytecode, encode_key, &options)),
                 Err(_) => Response::error("invalid bytecode", 400),
             }
         })
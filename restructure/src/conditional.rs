@@ -22,8 +22,8 @@ impl GraphStructurer {
     fn expand_if(if_stat: &mut ast::If) -> Option<ast::Block> {
         let mut then_block = if_stat.then_block.lock();
         let mut else_block = if_stat.else_block.lock();
-        let then_return = then_block.last().and_then(|x| x.as_return());
-        let else_return = else_block.last().and_then(|x| x.as_return());
+        let then_return = then_block.last().and_then(|x| x.inner().as_return());
+        let else_return = else_block.last().and_then(|x| x.inner().as_return());
         if let Some(then_return) = then_return
             && let Some(else_return) = else_return
         {
@@ -106,7 +106,7 @@ impl GraphStructurer {
                         .unwrap()
                         .map(|e| e.target());
                     let block = self.function.block_mut(n).unwrap();
-                    if let Some(if_stat) = block.last_mut().unwrap().as_if_mut() {
+                    if let Some(if_stat) = block.last_mut().unwrap().inner_mut().as_if_mut() {
                         if then_target == entry {
                             if_stat.then_block =
                                 Arc::new(Mutex::new(vec![ast::Continue {}.into()].into()));
@@ -149,7 +149,7 @@ impl GraphStructurer {
 
         let block = self.function.block_mut(entry).unwrap();
         // TODO: STYLE: rename to r#if?
-        let if_stat = block.last_mut().unwrap().as_if_mut().unwrap();
+        let if_stat = block.last_mut().unwrap().inner_mut().as_if_mut().unwrap();
         if_stat.then_block = Arc::new(then_block.into());
         if_stat.else_block = Arc::new(else_block.into());
         Self::simplify_if(if_stat);
@@ -206,7 +206,7 @@ impl GraphStructurer {
             let then_block = self.function.remove_block(then_node).unwrap();
 
             let block = self.function.block_mut(entry).unwrap();
-            let if_stat = block.last_mut().unwrap().as_if_mut().unwrap();
+            let if_stat = block.last_mut().unwrap().inner_mut().as_if_mut().unwrap();
             if_stat.then_block = Arc::new(then_block.into());
 
             if inverted {
@@ -296,7 +296,7 @@ impl GraphStructurer {
         let mut changed = false;
         let header_successors = self.function.successor_blocks(header).collect_vec();
         let block = self.function.block_mut(entry).unwrap();
-        if let Some(if_stat) = block.last_mut().unwrap().as_if_mut() {
+        if let Some(if_stat) = block.last_mut().unwrap().inner_mut().as_if_mut() {
             if then_node == header && !header_successors.contains(&entry) && then_main_cont {
                 if_stat.then_block = Arc::new(Mutex::new(vec![ast::Continue {}.into()].into()));
                 changed = true;
@@ -344,7 +344,7 @@ impl GraphStructurer {
         else_node: NodeIndex,
     ) -> bool {
         let block = self.function.block_mut(entry).unwrap();
-        if block.last_mut().unwrap().as_if_mut().is_none() {
+        if block.last_mut().unwrap().inner_mut().as_if_mut().is_none() {
             // for loops
             return false;
         }
@@ -0,0 +1,1200 @@
+use ast::{LocalRw, Traverse};
+use parking_lot::Mutex;
+use rustc_hash::{FxHashMap, FxHashSet};
+use triomphe::Arc;
+
+use crate::collect_labels;
+
+// Luau has no goto, so blocks that still contain gotos and labels after structuring are
+// rewritten into a loop that dispatches on a state variable:
+//
+// local state = 1
+// while true do
+//     if state == 1 then
+//         ...
+//         state = 2
+//         continue
+//     elseif state == 2 then
+//         ...
+//     end
+// end
+//
+// every label starts a new case and every goto becomes an assignment to the state variable
+// followed by a `continue`. statements that contain labels are lowered into cases of their own
+// since jumping into them can't be expressed otherwise. loops that only contain gotos are kept,
+// a goto in them becomes an assignment to the state variable followed by a `break` and the
+// loop is followed by a check whether the state changed:
+//
+// for k, v in t do
+//     state = 3
+//     break
+// end
+// if state ~= 1 then
+//     continue
+// end
+//
+// a loop whose labels are only jumped to from inside it is kept too, its body gets a dispatch
+// loop of its own. a `continue` leaves that dispatch loop, and a `break` leaves it through a case
+// that's checked for after it.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Case(usize),
+    // leave the dispatch loop, i.e. the end of the block it replaces
+    Exit,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LoopTargets {
+    r#break: Target,
+    r#continue: Target,
+}
+
+fn nested_blocks(statement: &ast::Statement) -> Vec<Arc<Mutex<ast::Block>>> {
+    match statement.inner() {
+        ast::Statement::If(r#if) => vec![r#if.then_block.clone(), r#if.else_block.clone()],
+        ast::Statement::While(r#while) => vec![r#while.block.clone()],
+        ast::Statement::Repeat(repeat) => vec![repeat.block.clone()],
+        ast::Statement::NumericFor(numeric_for) => vec![numeric_for.block.clone()],
+        ast::Statement::GenericFor(generic_for) => vec![generic_for.block.clone()],
+        _ => Vec::new(),
+    }
+}
+
+fn any_statement(block: &ast::Block, predicate: &impl Fn(&ast::Statement) -> bool) -> bool {
+    block.iter().any(|statement| {
+        predicate(statement)
+            || nested_blocks(statement)
+                .iter()
+                .any(|block| any_statement(&block.lock(), predicate))
+    })
+}
+
+fn is_goto_or_label(statement: &ast::Statement) -> bool {
+    matches!(
+        statement.inner(),
+        ast::Statement::Goto(_) | ast::Statement::Label(_)
+    )
+}
+
+fn is_loop(statement: &ast::Statement) -> bool {
+    matches!(
+        statement.inner(),
+        ast::Statement::While(_)
+            | ast::Statement::Repeat(_)
+            | ast::Statement::NumericFor(_)
+            | ast::Statement::GenericFor(_)
+    )
+}
+
+// statements that still contain a label have to be lowered, since something outside of them
+// jumps into them
+fn needs_lowering(statement: &ast::Statement) -> bool {
+    nested_blocks(statement).iter().any(|block| {
+        any_statement(&block.lock(), &|statement| {
+            matches!(statement.inner(), ast::Statement::Label(_))
+        })
+    })
+}
+
+// replaces the jump at `index` with `jump`, keeping the comments attached to it.
+// anything after the jump is unreachable and removed
+fn replace_with_jump(block: &mut ast::Block, index: usize, mut jump: Vec<ast::Statement>) {
+    let statement = block.0.drain(index..).next().unwrap();
+    if let ast::Statement::Commented(commented) = statement {
+        for comment in commented.leading {
+            ast::comment_before(jump.first_mut().unwrap(), comment);
+        }
+        for comment in commented.trailing {
+            ast::comment_after(jump.last_mut().unwrap(), comment);
+        }
+    }
+    block.extend(jump);
+}
+
+// the locals written in a block, the ones written in loops are added to `loop_locals` instead
+fn written_locals(
+    block: &ast::Block,
+    in_loop: bool,
+    locals: &mut Vec<ast::RcLocal>,
+    loop_locals: &mut Vec<ast::RcLocal>,
+) {
+    for statement in block.iter() {
+        let in_loop = in_loop || is_loop(statement);
+        let written = if in_loop {
+            &mut *loop_locals
+        } else {
+            &mut *locals
+        };
+        for local in statement.values_written() {
+            if !written.contains(local) {
+                written.push(local.clone());
+            }
+        }
+        for block in nested_blocks(statement) {
+            written_locals(&block.lock(), in_loop, locals, loop_locals);
+        }
+    }
+}
+
+fn read_locals(block: &ast::Block, locals: &mut FxHashSet<ast::RcLocal>) {
+    for statement in block.iter() {
+        locals.extend(statement.values_read().into_iter().cloned());
+        for block in nested_blocks(statement) {
+            read_locals(&block.lock(), locals);
+        }
+    }
+}
+
+// the locals captured by the closures in a block
+fn captured_locals(block: &ast::Block, locals: &mut FxHashSet<ast::RcLocal>) {
+    for statement in block.iter() {
+        let mut rvalues = statement.rvalues();
+        while let Some(rvalue) = rvalues.pop() {
+            if let ast::RValue::Closure(closure) = rvalue {
+                locals.extend(closure.upvalues.iter().map(|upvalue| match upvalue {
+                    ast::Upvalue::Copy(local) | ast::Upvalue::Ref(local) => local.clone(),
+                }));
+            }
+            rvalues.extend(rvalue.rvalues());
+        }
+        for block in nested_blocks(statement) {
+            captured_locals(&block.lock(), locals);
+        }
+    }
+}
+
+// whether `local` is assigned at the top of `block` before anything in it uses the local
+fn assigned_first(block: &ast::Block, local: &ast::RcLocal) -> bool {
+    for statement in block.iter() {
+        if statement.values_read().contains(&local) {
+            return false;
+        }
+        if statement.values_written().contains(&local) {
+            return true;
+        }
+        let mut used = FxHashSet::default();
+        for block in nested_blocks(statement) {
+            let (mut written, mut loop_written) = (Vec::new(), Vec::new());
+            written_locals(&block.lock(), false, &mut written, &mut loop_written);
+            read_locals(&block.lock(), &mut used);
+            used.extend(written.into_iter().chain(loop_written));
+        }
+        if used.contains(local) {
+            return false;
+        }
+    }
+    false
+}
+
+// the number of statements that use each local in a block, including nested blocks
+fn count_uses(block: &ast::Block, uses: &mut FxHashMap<ast::RcLocal, usize>) {
+    for statement in block.iter() {
+        let mut used = statement.values_read();
+        used.extend(statement.values_written());
+        for local in used.into_iter().collect::<FxHashSet<_>>() {
+            *uses.entry(local.clone()).or_default() += 1;
+        }
+        for block in nested_blocks(statement) {
+            count_uses(&block.lock(), uses);
+        }
+    }
+}
+
+// the number of gotos to each label in a block, including nested blocks
+fn count_gotos(block: &ast::Block, gotos: &mut FxHashMap<ast::Label, usize>) {
+    for statement in block.iter() {
+        if let ast::Statement::Goto(goto) = statement.inner() {
+            *gotos.entry(goto.0.clone()).or_default() += 1;
+        }
+        for block in nested_blocks(statement) {
+            count_gotos(&block.lock(), gotos);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Dispatcher {
+    state: ast::RcLocal,
+    // the labels in the block, gotos to any other label are left to an outer dispatcher
+    labels: FxHashSet<ast::Label>,
+    label_cases: FxHashMap<ast::Label, usize>,
+    cases: Vec<(usize, ast::Block)>,
+    referenced: FxHashSet<usize>,
+    case_count: usize,
+}
+
+impl Dispatcher {
+    fn new_case(&mut self) -> usize {
+        self.case_count += 1;
+        self.case_count
+    }
+
+    fn label_case(&mut self, label: &ast::Label) -> usize {
+        if let Some(&case) = self.label_cases.get(label) {
+            case
+        } else {
+            let case = self.new_case();
+            self.label_cases.insert(label.clone(), case);
+            case
+        }
+    }
+
+    fn set_state(&self, case: usize) -> ast::Statement {
+        ast::Assign::new(
+            vec![self.state.clone().into()],
+            vec![ast::Literal::Number(case as f64).into()],
+        )
+        .into()
+    }
+
+    fn jump(&mut self, target: Target) -> Vec<ast::Statement> {
+        match target {
+            Target::Case(case) => {
+                self.referenced.insert(case);
+                vec![self.set_state(case), ast::Continue {}.into()]
+            }
+            Target::Exit => vec![ast::Break {}.into()],
+        }
+    }
+
+    // the jump that replaces `statement`, if it's a goto to one of the labels or a break or
+    // continue of a lowered loop or of the loop the block is the body of
+    fn jump_target(
+        &mut self,
+        statement: &ast::Statement,
+        loop_targets: Option<LoopTargets>,
+    ) -> Option<Target> {
+        match (statement.inner(), loop_targets) {
+            (ast::Statement::Goto(goto), _) if self.labels.contains(&goto.0) => {
+                Some(Target::Case(self.label_case(&goto.0)))
+            }
+            (ast::Statement::Break(_), Some(loop_targets)) => Some(loop_targets.r#break),
+            (ast::Statement::Continue(_), Some(loop_targets)) => Some(loop_targets.r#continue),
+            _ => None,
+        }
+    }
+
+    // whether `statement` is replaced by jumps when its block is lowered
+    fn is_replaced(&self, statement: &ast::Statement, loop_targets: Option<LoopTargets>) -> bool {
+        match (statement.inner(), loop_targets) {
+            (ast::Statement::Label(_), _)
+            | (ast::Statement::Break(_) | ast::Statement::Continue(_), Some(_)) => true,
+            (ast::Statement::Goto(goto), _) => self.labels.contains(&goto.0),
+            _ => needs_lowering(statement),
+        }
+    }
+
+    // `if state ~= case then <exit> end`, the state only changes when a goto in a loop is taken
+    fn state_changed(&self, case: usize, exit: ast::Statement) -> ast::Statement {
+        ast::If::new(
+            ast::Binary::new(
+                self.state.clone().into(),
+                ast::Literal::Number(case as f64).into(),
+                ast::BinaryOperation::NotEqual,
+            )
+            .into(),
+            ast::Block(vec![exit]),
+            ast::Block::default(),
+        )
+        .into()
+    }
+
+    // rewrites the jumps in a block of `case` that is kept as is
+    fn rewrite_jumps(
+        &mut self,
+        block: &mut ast::Block,
+        case: usize,
+        loop_targets: Option<LoopTargets>,
+    ) {
+        let mut index = 0;
+        while index < block.len() {
+            if let Some(target) = self.jump_target(&block[index], loop_targets) {
+                let jump = self.jump(target);
+                replace_with_jump(block, index, jump);
+                break;
+            }
+            if let ast::Statement::If(r#if) = block[index].inner() {
+                self.rewrite_jumps(&mut r#if.then_block.lock(), case, loop_targets);
+                self.rewrite_jumps(&mut r#if.else_block.lock(), case, loop_targets);
+            } else if self.rewrite_loop_gotos(&block[index], case) {
+                index += 1;
+                block.insert(index, self.state_changed(case, ast::Continue {}.into()));
+            }
+            index += 1;
+        }
+    }
+
+    // rewrites the gotos in a loop of `case` that is kept as is, breaks and continues refer to
+    // the loop itself. returns whether the loop contains a goto
+    fn rewrite_loop_gotos(&mut self, statement: &ast::Statement, case: usize) -> bool {
+        if !is_loop(statement) {
+            return false;
+        }
+        let mut found = false;
+        for block in nested_blocks(statement) {
+            found |= self.rewrite_gotos_in_loop(&mut block.lock(), case);
+        }
+        found
+    }
+
+    fn rewrite_gotos_in_loop(&mut self, block: &mut ast::Block, case: usize) -> bool {
+        let mut found = false;
+        let mut index = 0;
+        while index < block.len() {
+            if let ast::Statement::Goto(goto) = block[index].inner()
+                && self.labels.contains(&goto.0)
+            {
+                let target = self.label_case(&goto.0);
+                self.referenced.insert(target);
+                let jump = vec![self.set_state(target), ast::Break {}.into()];
+                replace_with_jump(block, index, jump);
+                return true;
+            }
+            if let ast::Statement::If(r#if) = block[index].inner() {
+                found |= self.rewrite_gotos_in_loop(&mut r#if.then_block.lock(), case);
+                found |= self.rewrite_gotos_in_loop(&mut r#if.else_block.lock(), case);
+            } else if self.rewrite_loop_gotos(&block[index], case) {
+                // leave the enclosing loop too
+                found = true;
+                index += 1;
+                block.insert(index, self.state_changed(case, ast::Break {}.into()));
+            }
+            index += 1;
+        }
+        found
+    }
+
+    // lowers `block` into cases, starting with `case` and jumping to `next` once it completes
+    fn lower_block(
+        &mut self,
+        block: ast::Block,
+        case: usize,
+        next: Target,
+        loop_targets: Option<LoopTargets>,
+    ) {
+        let mut current = (case, ast::Block::default());
+        let mut terminated = false;
+        for statement in block.0 {
+            // a statement that's replaced by jumps keeps its comments as standalone ones
+            let (statement, trailing) = match statement {
+                ast::Statement::Commented(commented)
+                    if self.is_replaced(&commented.statement, loop_targets) =>
+                {
+                    current
+                        .1
+                        .extend(commented.leading.into_iter().map(ast::Statement::from));
+                    (*commented.statement, commented.trailing)
+                }
+                statement => (statement, Vec::new()),
+            };
+            if let ast::Statement::Label(label) = &statement {
+                let label_case = self.label_case(label);
+                if !terminated {
+                    let jump = self.jump(Target::Case(label_case));
+                    current.1.extend(jump);
+                }
+                self.cases.push(std::mem::replace(
+                    &mut current,
+                    (label_case, ast::Block::default()),
+                ));
+                terminated = false;
+                current
+                    .1
+                    .extend(trailing.into_iter().map(ast::Statement::from));
+                continue;
+            }
+            if terminated {
+                // unreachable until the next label, this case is removed unless something jumps to it
+                let dead_case = self.new_case();
+                self.cases.push(std::mem::replace(
+                    &mut current,
+                    (dead_case, ast::Block::default()),
+                ));
+                terminated = false;
+            }
+
+            if needs_lowering(&statement) {
+                let after = self.new_case();
+                self.lower_statement(statement, &mut current.1, after, loop_targets);
+                self.cases.push(std::mem::replace(
+                    &mut current,
+                    (after, ast::Block::default()),
+                ));
+            } else if let Some(target) = self.jump_target(&statement, loop_targets) {
+                let jump = self.jump(target);
+                current.1.extend(jump);
+                terminated = true;
+            } else {
+                if let ast::Statement::If(r#if) = statement.inner() {
+                    self.rewrite_jumps(&mut r#if.then_block.lock(), current.0, loop_targets);
+                    self.rewrite_jumps(&mut r#if.else_block.lock(), current.0, loop_targets);
+                }
+                let left_by_goto = self.rewrite_loop_gotos(&statement, current.0);
+                terminated = matches!(statement.inner(), ast::Statement::Return(_));
+                current.1.push(statement);
+                if left_by_goto {
+                    let exit = self.state_changed(current.0, ast::Continue {}.into());
+                    current.1.push(exit);
+                }
+            }
+            current
+                .1
+                .extend(trailing.into_iter().map(ast::Statement::from));
+        }
+        if !terminated {
+            let jump = self.jump(next);
+            current.1.extend(jump);
+        }
+        self.cases.push(current);
+    }
+
+    // lowers a statement containing labels or gotos into cases, the statement is replaced by a jump
+    // to its first case in `block` and it jumps to `after` once it completes
+    fn lower_statement(
+        &mut self,
+        statement: ast::Statement,
+        block: &mut ast::Block,
+        after: usize,
+        loop_targets: Option<LoopTargets>,
+    ) {
+        let take = |block: &Arc<Mutex<ast::Block>>| std::mem::take(&mut *block.lock());
+        match statement {
+            ast::Statement::If(r#if) => {
+                let then_block = take(&r#if.then_block);
+                let else_block = take(&r#if.else_block);
+                let then_case = self.new_case();
+                let else_case = if else_block.is_empty() {
+                    after
+                } else {
+                    self.new_case()
+                };
+                let then_jump = self.jump(Target::Case(then_case));
+                block.push(
+                    ast::If::new(r#if.condition, then_jump.into(), ast::Block::default()).into(),
+                );
+                let else_jump = self.jump(Target::Case(else_case));
+                block.extend(else_jump);
+                self.lower_block(then_block, then_case, Target::Case(after), loop_targets);
+                if else_case != after {
+                    self.lower_block(else_block, else_case, Target::Case(after), loop_targets);
+                }
+            }
+            ast::Statement::While(r#while) => {
+                let header_case = self.new_case();
+                let body_case = self.new_case();
+                let jump = self.jump(Target::Case(header_case));
+                block.extend(jump);
+
+                let body_jump = self.jump(Target::Case(body_case));
+                let mut header = ast::Block(vec![ast::If::new(
+                    r#while.condition,
+                    body_jump.into(),
+                    ast::Block::default(),
+                )
+                .into()]);
+                header.extend(self.jump(Target::Case(after)));
+                self.cases.push((header_case, header));
+
+                self.lower_block(
+                    take(&r#while.block),
+                    body_case,
+                    Target::Case(header_case),
+                    Some(LoopTargets {
+                        r#break: Target::Case(after),
+                        r#continue: Target::Case(header_case),
+                    }),
+                );
+            }
+            ast::Statement::Repeat(repeat) => {
+                let body_case = self.new_case();
+                let condition_case = self.new_case();
+                let jump = self.jump(Target::Case(body_case));
+                block.extend(jump);
+
+                self.lower_block(
+                    take(&repeat.block),
+                    body_case,
+                    Target::Case(condition_case),
+                    Some(LoopTargets {
+                        r#break: Target::Case(after),
+                        r#continue: Target::Case(condition_case),
+                    }),
+                );
+
+                let after_jump = self.jump(Target::Case(after));
+                let mut condition = ast::Block(vec![ast::If::new(
+                    repeat.condition,
+                    after_jump.into(),
+                    ast::Block::default(),
+                )
+                .into()]);
+                condition.extend(self.jump(Target::Case(body_case)));
+                self.cases.push((condition_case, condition));
+            }
+            ast::Statement::NumericFor(numeric_for) => {
+                let (index, limit, step) = <(ast::RcLocal, ast::RcLocal, ast::RcLocal)>::default();
+                let header_case = self.new_case();
+                let body_case = self.new_case();
+                let increment_case = self.new_case();
+                block.push(
+                    ast::Assign::new(
+                        vec![
+                            index.clone().into(),
+                            limit.clone().into(),
+                            step.clone().into(),
+                        ],
+                        vec![numeric_for.initial, numeric_for.limit, numeric_for.step],
+                    )
+                    .into(),
+                );
+                let jump = self.jump(Target::Case(header_case));
+                block.extend(jump);
+
+                // (step > 0 and index <= limit) or (step <= 0 and index >= limit)
+                let compare = |left: &ast::RcLocal, right: ast::RValue, operation| {
+                    ast::RValue::from(ast::Binary::new(left.clone().into(), right, operation))
+                };
+                let zero = || ast::RValue::from(ast::Literal::Number(0.0));
+                let condition = ast::Binary::new(
+                    ast::Binary::new(
+                        compare(&step, zero(), ast::BinaryOperation::GreaterThan),
+                        compare(
+                            &index,
+                            limit.clone().into(),
+                            ast::BinaryOperation::LessThanOrEqual,
+                        ),
+                        ast::BinaryOperation::And,
+                    )
+                    .into(),
+                    ast::Binary::new(
+                        compare(&step, zero(), ast::BinaryOperation::LessThanOrEqual),
+                        compare(
+                            &index,
+                            limit.clone().into(),
+                            ast::BinaryOperation::GreaterThanOrEqual,
+                        ),
+                        ast::BinaryOperation::And,
+                    )
+                    .into(),
+                    ast::BinaryOperation::Or,
+                );
+                let mut then_block = ast::Block(vec![ast::Assign::new(
+                    vec![numeric_for.counter.into()],
+                    vec![index.clone().into()],
+                )
+                .into()]);
+                then_block.extend(self.jump(Target::Case(body_case)));
+                let mut header = ast::Block(vec![ast::If::new(
+                    condition.into(),
+                    then_block,
+                    ast::Block::default(),
+                )
+                .into()]);
+                header.extend(self.jump(Target::Case(after)));
+                self.cases.push((header_case, header));
+
+                let mut increment = ast::Block(vec![ast::Assign::new(
+                    vec![index.clone().into()],
+                    vec![
+                        ast::Binary::new(index.into(), step.into(), ast::BinaryOperation::Add)
+                            .into(),
+                    ],
+                )
+                .into()]);
+                increment.extend(self.jump(Target::Case(header_case)));
+                self.cases.push((increment_case, increment));
+
+                self.lower_block(
+                    take(&numeric_for.block),
+                    body_case,
+                    Target::Case(increment_case),
+                    Some(LoopTargets {
+                        r#break: Target::Case(after),
+                        r#continue: Target::Case(increment_case),
+                    }),
+                );
+            }
+            // generic fors are kept by `eliminate_in_loops`
+            _ => unreachable!(),
+        }
+    }
+}
+
+// lowers `block` into a dispatch loop, `in_loop` is whether it's the body of a loop whose breaks
+// and continues have to leave the dispatch loop. `outside` are the locals read after the block
+// in the same scope, `enclosing` the ones used before or after its scope that can't be declared
+// in it
+fn dispatch(
+    block: ast::Block,
+    in_loop: bool,
+    outside: &FxHashSet<ast::RcLocal>,
+    enclosing: &FxHashSet<ast::RcLocal>,
+) -> ast::Block {
+    let mut dispatcher = Dispatcher::default();
+    collect_labels(&block, &mut dispatcher.labels);
+    let entry = dispatcher.new_case();
+    dispatcher.referenced.insert(entry);
+    let break_case = in_loop.then(|| {
+        let case = dispatcher.new_case();
+        dispatcher
+            .cases
+            .push((case, ast::Block(vec![ast::Break {}.into()])));
+        case
+    });
+    let loop_targets = break_case.map(|case| LoopTargets {
+        r#break: Target::Case(case),
+        r#continue: Target::Exit,
+    });
+    dispatcher.lower_block(block, entry, Target::Exit, loop_targets);
+
+    let Dispatcher {
+        state,
+        mut cases,
+        referenced,
+        ..
+    } = dispatcher;
+    cases.retain(|(case, _)| referenced.contains(case));
+    cases.sort_unstable_by_key(|&(case, _)| case);
+
+    // cases can run in any order, so the locals they write have to be declared outside of the
+    // dispatch loop. parameters and upvalues are removed from this declaration by `LocalDeclarer`.
+    // locals written in loops are only declared outside if another case reads them
+    let mut locals = Vec::new();
+    let mut loop_locals = Vec::new();
+    let mut used = Vec::with_capacity(cases.len());
+    let mut captured = FxHashSet::default();
+    for (case, block) in &cases {
+        let mut case_locals = Vec::new();
+        let mut case_loop_locals = Vec::new();
+        written_locals(block, false, &mut case_locals, &mut case_loop_locals);
+        let mut case_used = FxHashSet::default();
+        read_locals(block, &mut case_used);
+        case_used.extend(case_locals.iter().chain(&case_loop_locals).cloned());
+        for local in case_locals {
+            if !locals.contains(&local) {
+                locals.push(local);
+            }
+        }
+        loop_locals.push((*case, case_loop_locals));
+        used.push(case_used);
+        captured_locals(block, &mut captured);
+    }
+    for (case, case_loop_locals) in loop_locals {
+        for local in case_loop_locals {
+            if !locals.contains(&local)
+                && (outside.contains(&local)
+                    || cases
+                        .iter()
+                        .zip(&used)
+                        .any(|((other, _), used)| *other != case && used.contains(&local)))
+            {
+                locals.push(local);
+            }
+        }
+    }
+    locals.retain(|local| local != &state && !enclosing.contains(local));
+    // a local captured by a closure is declared in its case if no other case uses it, so every
+    // pass through the case creates a new one for the closure like the original code did
+    let declared_in_case = |local: &ast::RcLocal| {
+        let mut using = cases
+            .iter()
+            .zip(&used)
+            .filter(|(_, used)| used.contains(local));
+        captured.contains(local)
+            && !outside.contains(local)
+            && match (using.next(), using.next()) {
+                (Some(((_, block), _)), None) => assigned_first(block, local),
+                _ => false,
+            }
+    };
+    locals.retain(|local| !declared_in_case(local));
+
+    let mut dispatch = ast::Block::default();
+    for (case, block) in cases.into_iter().rev() {
+        let condition = ast::Binary::new(
+            state.clone().into(),
+            ast::Literal::Number(case as f64).into(),
+            ast::BinaryOperation::Equal,
+        );
+        dispatch = ast::Block(vec![ast::If::new(condition.into(), block, dispatch).into()]);
+    }
+
+    let mut block = ast::Block::default();
+    if !locals.is_empty() {
+        let mut declaration =
+            ast::Assign::new(locals.into_iter().map(|l| l.into()).collect(), Vec::new());
+        declaration.prefix = true;
+        block.push(declaration.into());
+    }
+    block.push(
+        ast::Assign::new(
+            vec![state.clone().into()],
+            vec![ast::Literal::Number(entry as f64).into()],
+        )
+        .into(),
+    );
+    block.push(ast::While::new(ast::Literal::Boolean(true).into(), dispatch).into());
+    if let Some(case) = break_case
+        && referenced.contains(&case)
+    {
+        let condition = ast::Binary::new(
+            state.into(),
+            ast::Literal::Number(case as f64).into(),
+            ast::BinaryOperation::Equal,
+        );
+        block.push(
+            ast::If::new(
+                condition.into(),
+                ast::Block(vec![ast::Break {}.into()]),
+                ast::Block::default(),
+            )
+            .into(),
+        );
+    }
+    block
+}
+
+// the gotos in a loop that's only jumped into from inside it are eliminated in its body, so the
+// loop itself is kept. `gotos` is the number of gotos to each label and `uses` the number of
+// statements using each local in the whole function.
+// a generic for always is, Luau bytecode can't enter its body without going through its header
+fn eliminate_in_loops(
+    block: &ast::Block,
+    gotos: &FxHashMap<ast::Label, usize>,
+    uses: &FxHashMap<ast::RcLocal, usize>,
+) {
+    for statement in block.iter() {
+        for block in nested_blocks(statement) {
+            eliminate_in_loops(&block.lock(), gotos, uses);
+        }
+        if !is_loop(statement) {
+            continue;
+        }
+        let body = nested_blocks(statement).pop().unwrap();
+        let mut labels = FxHashSet::default();
+        collect_labels(&body.lock(), &mut labels);
+        if labels.is_empty() {
+            continue;
+        }
+        let mut inner_gotos = FxHashMap::default();
+        count_gotos(&body.lock(), &mut inner_gotos);
+        if matches!(statement.inner(), ast::Statement::GenericFor(_))
+            || labels
+                .iter()
+                .all(|label| inner_gotos.get(label) == gotos.get(label))
+        {
+            // the condition of a `repeat` loop can use the locals in its body
+            let mut outside = FxHashSet::default();
+            if let ast::Statement::Repeat(repeat) = statement.inner() {
+                outside.extend(repeat.condition.values_read().into_iter().cloned());
+            }
+            // the locals used outside of the body are declared outside of the loop. the
+            // dispatch loops of nested loops only add uses, so this can't undercount
+            let mut body_uses = FxHashMap::default();
+            count_uses(&body.lock(), &mut body_uses);
+            let enclosing = body_uses
+                .into_iter()
+                .filter(|(local, count)| {
+                    count + usize::from(outside.contains(local))
+                        < uses.get(local).copied().unwrap_or_default()
+                })
+                .map(|(local, _)| local)
+                .collect();
+            let mut body = body.lock();
+            *body = dispatch(std::mem::take(&mut body), true, &outside, &enclosing);
+        }
+    }
+}
+
+/// Replaces the gotos and labels in a block with a loop that dispatches on a state variable.
+pub(crate) fn eliminate_gotos(block: ast::Block) -> ast::Block {
+    if !any_statement(&block, &is_goto_or_label) {
+        return block;
+    }
+
+    let mut gotos = FxHashMap::default();
+    count_gotos(&block, &mut gotos);
+    let mut uses = FxHashMap::default();
+    count_uses(&block, &mut uses);
+    eliminate_in_loops(&block, &gotos, &uses);
+    if !any_statement(&block, &is_goto_or_label) {
+        return block;
+    }
+    dispatch(block, false, &FxHashSet::default(), &FxHashSet::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use ast::{local_declarations::LocalDeclarer, name_locals::name_locals};
+    use by_address::ByAddress;
+
+    use super::*;
+
+    fn local(name: &str) -> ast::RcLocal {
+        ast::RcLocal::new(ast::Local::new(Some(name.to_string())))
+    }
+
+    fn global(name: &str) -> ast::RValue {
+        ast::Global::new(name.as_bytes().to_vec()).into()
+    }
+
+    fn print(values: Vec<ast::RValue>) -> ast::Statement {
+        ast::Call::new(global("print"), values).into()
+    }
+
+    fn goto(label: &str) -> ast::Statement {
+        ast::Goto::new(label.into()).into()
+    }
+
+    fn label(label: &str) -> ast::Statement {
+        ast::Label::from(label).into()
+    }
+
+    fn eliminate(block: ast::Block) -> String {
+        let block = Arc::new(Mutex::new(eliminate_gotos(block)));
+        LocalDeclarer::default().declare_locals(block.clone(), &FxHashSet::default());
+        let mut block = Arc::try_unwrap(block).unwrap().into_inner();
+        name_locals(&mut block, false, false);
+        block.to_string()
+    }
+
+    #[test]
+    fn goto_out_of_generic_for() {
+        let (k, v) = (local("k"), local("v"));
+        let block = ast::Block(vec![
+            ast::GenericFor::new(
+                vec![k.clone(), v.clone()],
+                vec![global("t")],
+                ast::Block(vec![
+                    ast::If::new(v.into(), vec![goto("done")].into(), ast::Block::default()).into(),
+                    print(vec![k.into()]),
+                ]),
+            )
+            .into(),
+            print(vec![global("a")]),
+            label("done"),
+            print(vec![global("b")]),
+        ]);
+        // the loop is kept, `t` can be a table
+        assert_eq!(
+            eliminate(block),
+            "\
+local v1 = 1
+while true do
+	if v1 == 1 then
+		for k, v in t do
+			if v then
+				v1 = 2
+				break
+			end
+			print(k)
+		end
+		if v1 ~= 1 then
+			continue
+		end
+		print(a)
+		v1 = 2
+		continue
+	elseif v1 == 2 then
+		print(b)
+		break
+	end
+end"
+        );
+    }
+
+    #[test]
+    fn label_in_generic_for() {
+        let (k, v) = (local("k"), local("v"));
+        let block = ast::Block(vec![ast::GenericFor::new(
+            vec![k.clone(), v.clone()],
+            vec![global("t")],
+            ast::Block(vec![
+                ast::If::new(
+                    k.clone().into(),
+                    vec![goto("skip")].into(),
+                    ast::Block::default(),
+                )
+                .into(),
+                print(vec![k.into()]),
+                label("skip"),
+                print(vec![v.into()]),
+            ]),
+        )
+        .into()]);
+        // the loop is kept and its body gets a dispatch loop of its own
+        assert_eq!(
+            eliminate(block),
+            "\
+for k, v in t do
+	local v1 = 1
+	while true do
+		if v1 == 1 then
+			if k then
+				v1 = 3
+				continue
+			end
+			print(k)
+			v1 = 3
+			continue
+		elseif v1 == 3 then
+			print(v)
+			break
+		end
+	end
+end"
+        );
+    }
+
+    #[test]
+    fn break_from_loop_with_label() {
+        let i = local("i");
+        let block = ast::Block(vec![ast::NumericFor::new(
+            ast::Literal::Number(1.0).into(),
+            ast::Literal::Number(10.0).into(),
+            ast::Literal::Number(1.0).into(),
+            i.clone(),
+            ast::Block(vec![
+                ast::If::new(
+                    global("c"),
+                    vec![goto("skip")].into(),
+                    ast::Block::default(),
+                )
+                .into(),
+                ast::If::new(
+                    global("d"),
+                    vec![ast::Break {}.into()].into(),
+                    ast::Block::default(),
+                )
+                .into(),
+                print(vec![i.clone().into()]),
+                label("skip"),
+                ast::If::new(
+                    global("e"),
+                    vec![ast::Continue {}.into()].into(),
+                    ast::Block::default(),
+                )
+                .into(),
+                print(vec![i.into()]),
+            ]),
+        )
+        .into()]);
+        // `continue` leaves the dispatch loop, `break` leaves the `for` loop after it
+        assert_eq!(
+            eliminate(block),
+            "\
+for i = 1, 10 do
+	local v1 = 1
+	while true do
+		if v1 == 1 then
+			if c then
+				v1 = 3
+				continue
+			end
+			if d then
+				v1 = 2
+				continue
+			end
+			print(i)
+			v1 = 3
+			continue
+		elseif v1 == 2 then
+			break
+		elseif v1 == 3 then
+			if e then
+				break
+			end
+			print(i)
+			break
+		end
+	end
+	if v1 == 2 then
+		break
+	end
+end"
+        );
+    }
+
+    #[test]
+    fn local_declared_before_loop() {
+        let (i, s) = (local("i"), local("s"));
+        let block = ast::Block(vec![
+            ast::Assign::new(
+                vec![s.clone().into()],
+                vec![ast::Literal::Number(0.0).into()],
+            )
+            .into(),
+            ast::NumericFor::new(
+                ast::Literal::Number(1.0).into(),
+                ast::Literal::Number(10.0).into(),
+                ast::Literal::Number(1.0).into(),
+                i.clone(),
+                ast::Block(vec![
+                    ast::If::new(
+                        global("c"),
+                        vec![goto("skip")].into(),
+                        ast::Block::default(),
+                    )
+                    .into(),
+                    ast::Assign::new(
+                        vec![s.clone().into()],
+                        vec![ast::Binary::new(
+                            s.clone().into(),
+                            i.into(),
+                            ast::BinaryOperation::Add,
+                        )
+                        .into()],
+                    )
+                    .into(),
+                    label("skip"),
+                ]),
+            )
+            .into(),
+            print(vec![s.into()]),
+        ]);
+        // `s` is declared before the loop, so the dispatch loop can't declare it again
+        assert_eq!(
+            eliminate(block),
+            "\
+local s = 0
+for i = 1, 10 do
+	local v1 = 1
+	while true do
+		if v1 == 1 then
+			if c then
+				v1 = 3
+				continue
+			end
+			s = s + i
+			v1 = 3
+			continue
+		elseif v1 == 3 then
+			break
+		end
+	end
+end
+print(s)"
+        );
+    }
+
+    #[test]
+    fn local_captured_in_goto_loop() {
+        let x = local("x");
+        let function = ast::Function {
+            body: ast::Block(vec![ast::Return::new(vec![x.clone().into()]).into()]),
+            ..Default::default()
+        };
+        let closure = ast::Closure {
+            function: ByAddress(Arc::new(Mutex::new(function))),
+            upvalues: vec![ast::Upvalue::Ref(x.clone())],
+        };
+        let block = ast::Block(vec![
+            label("again"),
+            ast::Assign::new(vec![x.into()], vec![global("i")]).into(),
+            print(vec![closure.into()]),
+            ast::If::new(
+                global("c"),
+                vec![goto("again")].into(),
+                ast::Block::default(),
+            )
+            .into(),
+        ]);
+        // every closure gets its own `x`
+        assert_eq!(
+            eliminate(block),
+            "\
+local v1 = 1
+while true do
+	if v1 == 1 then
+		v1 = 2
+		continue
+	elseif v1 == 2 then
+		local x = i
+		print(function()
+			-- upvalues: (ref) x
+			return x
+		end)
+		if c then
+			v1 = 2
+			continue
+		end
+		break
+	end
+end"
+        );
+    }
+
+    #[test]
+    fn local_written_in_loop_and_read_in_another_case() {
+        let x = local("x");
+        let block = ast::Block(vec![
+            ast::While::new(
+                global("c"),
+                ast::Block(vec![
+                    ast::Assign::new(
+                        vec![x.clone().into()],
+                        vec![ast::Literal::Number(1.0).into()],
+                    )
+                    .into(),
+                    ast::Break {}.into(),
+                ]),
+            )
+            .into(),
+            goto("print"),
+            label("print"),
+            print(vec![x.into()]),
+        ]);
+        // `x` keeps its value when the dispatch loop moves on to the next case
+        assert_eq!(
+            eliminate(block),
+            "\
+local x
+local v1 = 1
+while true do
+	if v1 == 1 then
+		while c do
+			x = 1
+			break
+		end
+		v1 = 2
+		continue
+	elseif v1 == 2 then
+		print(x)
+		break
+	end
+end"
+        );
+    }
+
+    #[test]
+    fn commented_goto() {
+        let mut jump = goto("l");
+        ast::comment_after(&mut jump, ast::Comment::new("a".to_string()));
+        let block = ast::Block(vec![
+            ast::If::new(global("c"), ast::Block(vec![jump]), ast::Block::default()).into(),
+            print(vec![global("b")]),
+            label("l"),
+            print(vec![global("d")]),
+        ]);
+        assert_eq!(
+            eliminate(block),
+            "\
+local v1 = 1
+while true do
+	if v1 == 1 then
+		if c then
+			v1 = 2
+			continue -- a
+		end
+		print(b)
+		v1 = 2
+		continue
+	elseif v1 == 2 then
+		print(d)
+		break
+	end
+end"
+        );
+    }
+}
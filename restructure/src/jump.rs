@@ -15,7 +15,7 @@ impl super::GraphStructurer {
     pub(crate) fn try_remove_unnecessary_condition(&mut self, node: NodeIndex) -> bool {
         let block = self.function.block(node).unwrap();
         if !block.is_empty()
-            && block.last().unwrap().as_if().is_some()
+            && block.last().unwrap().inner().as_if().is_some()
             && let Some((then_edge, else_edge)) = self.function.conditional_edges(node)
             && then_edge.target() == else_edge.target()
         {
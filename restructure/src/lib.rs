@@ -12,8 +12,11 @@ use petgraph::{
 use tuple::Map;
 
 mod conditional;
+mod dispatch;
 mod jump;
 mod r#loop;
+mod search;
+mod split;
 
 // TODO: REFACTOR: move
 pub fn post_dominators<N: Default, E: Default>(
@@ -32,30 +35,128 @@ pub fn post_dominators<N: Default, E: Default>(
     res
 }
 
+/// Options for [`lift_with_options`].
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The maximum number of alternative structurings to try when gotos have to be inserted,
+    /// picking the one with the least gotos. 0 inserts gotos greedily.
+    pub goto_search_budget: usize,
+    /// Replace gotos and labels that remain after structuring with a loop dispatching on a
+    /// state variable, for targets without goto such as Luau.
+    pub eliminate_gotos: bool,
+    /// The maximum number of statements in a block that may be duplicated to make irreducible
+    /// loops reducible before structuring. 0 disables node splitting.
+    pub split_threshold: usize,
+    /// Output blocks that can never be executed in a `--[[ dead code ]]` comment instead of
+    /// dropping them.
+    pub emit_dead_code: bool,
+    /// The target supports `continue`, so branches at the end of a loop body can be turned
+    /// into guard clauses. Otherwise `continue` is lowered to a `break` out of a
+    /// `repeat ... until true` loop around the loop body.
+    pub allow_continue: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            goto_search_budget: 32,
+            eliminate_gotos: false,
+            split_threshold: 8,
+            emit_dead_code: false,
+            allow_continue: false,
+        }
+    }
+}
+
+/// Statistics about a function lifted with [`lift_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct Statistics {
+    /// The number of unreachable blocks that were removed.
+    pub dead_blocks: usize,
+}
+
+// the labels targeted by gotos in a block, including nested blocks
+fn collect_gotos(block: &ast::Block, gotos: &mut FxHashSet<ast::Label>) {
+    collect_jumps(block, gotos, |statement| {
+        statement.as_goto().map(|goto| &goto.0)
+    });
+}
+
+// the labels defined in a block, including nested blocks
+fn collect_labels(block: &ast::Block, labels: &mut FxHashSet<ast::Label>) {
+    collect_jumps(block, labels, |statement| statement.as_label());
+}
+
+fn collect_jumps(
+    block: &ast::Block,
+    labels: &mut FxHashSet<ast::Label>,
+    label: fn(&ast::Statement) -> Option<&ast::Label>,
+) {
+    for statement in &block.0 {
+        let statement = statement.inner();
+        if let Some(label) = label(statement) {
+            labels.insert(label.clone());
+        }
+        match statement {
+            ast::Statement::If(r#if) => {
+                collect_jumps(&r#if.then_block.lock(), labels, label);
+                collect_jumps(&r#if.else_block.lock(), labels, label);
+            }
+            ast::Statement::While(r#while) => {
+                collect_jumps(&r#while.block.lock(), labels, label);
+            }
+            ast::Statement::Repeat(repeat) => {
+                collect_jumps(&repeat.block.lock(), labels, label);
+            }
+            ast::Statement::NumericFor(numeric_for) => {
+                collect_jumps(&numeric_for.block.lock(), labels, label);
+            }
+            ast::Statement::GenericFor(generic_for) => {
+                collect_jumps(&generic_for.block.lock(), labels, label);
+            }
+            _ => {}
+        }
+    }
+}
+
 struct GraphStructurer {
     pub function: Function,
     loop_headers: FxHashSet<NodeIndex>,
     label_to_node: FxHashMap<ast::Label, NodeIndex>,
+    options: Options,
+    // whether irreducible loops were split, see `find_loop_headers`
+    split: bool,
 }
 
 impl GraphStructurer {
     fn find_loop_headers(&mut self) {
         self.loop_headers.clear();
+        let dominators = simple_fast(self.function.graph(), self.function.entry().unwrap());
         depth_first_search(
             self.function.graph(),
             Some(self.function.entry().unwrap()),
             |event| {
-                if let DfsEvent::BackEdge(_, header) = event {
+                // once nodes were split, a back edge whose target does not dominate its source
+                // enters an irreducible loop that was too large to split. it can't be collapsed,
+                // so it's left to goto insertion instead.
+                if let DfsEvent::BackEdge(source, header) = event
+                    && (!self.split
+                        || dominators
+                            .dominators(source)
+                            .is_some_and(|mut d| d.any(|d| d == header)))
+                {
                     self.loop_headers.insert(header);
                 }
             },
         );
     }
-    fn new(function: Function) -> Self {
+    fn new(function: Function, options: Options) -> Self {
         let mut this = Self {
             function,
             loop_headers: FxHashSet::default(),
             label_to_node: FxHashMap::default(),
+            options,
+            split: false,
         };
         this.find_loop_headers();
         this
@@ -121,6 +222,10 @@ impl GraphStructurer {
 
         let mut changed = false;
         while let Some(node) = dfs_postorder.next(self.function.graph()) {
+            // node may have been removed by a previous match
+            if !self.function.has_block(node) {
+                continue;
+            }
             // println!("matching {:?}", node);
             let matched = self.try_match_pattern(node, &dominators, &post_dom);
             if matched {
@@ -149,7 +254,7 @@ impl GraphStructurer {
                     .block(node)
                     .unwrap()
                     .first()
-                    .and_then(|s| s.as_label())
+                    .and_then(|s| s.inner().as_label())
                     .is_none()
                 {
                     self.function.remove_block(node);
@@ -179,7 +284,11 @@ impl GraphStructurer {
             // TODO: make label an Rc and have a global counter for block name
             let label = ast::Label(format!("l{}", target.index()));
             let target_block = self.function.block_mut(target).unwrap();
-            if target_block.first().and_then(|s| s.as_label()).is_none() {
+            if target_block
+                .first()
+                .and_then(|s| s.inner().as_label())
+                .is_none()
+            {
                 self.label_to_node.insert(label.clone(), target);
                 target_block.insert(0, label.clone().into());
             }
@@ -195,7 +304,9 @@ impl GraphStructurer {
     }
 
     fn remove_last_return(block: ast::Block) -> ast::Block {
-        if let Some(ast::Statement::Return(last_statement)) = block.last() {
+        if let Some(ast::Statement::Return(last_statement)) =
+            block.last().map(ast::Statement::inner)
+        {
             if last_statement.values.is_empty() {
                 let take = block.len() - 1;
                 return block.0.into_iter().take(take).collect_vec().into();
@@ -210,12 +321,16 @@ impl GraphStructurer {
             if self.function.graph().node_count() == 1 {
                 break;
             }
+            if let Some(edge) = self.search_goto_edge() {
+                self.insert_goto_for_edge(edge);
+                self.find_loop_headers();
+                continue;
+            }
             // last resort refinement
             let edges = self.function.graph().edge_indices().collect::<Vec<_>>();
             // https://edmcman.github.io/papers/usenix13.pdf
             // we prefer to remove edges whose source does not dominate its target, nor whose target dominates its source
-            // TODO: try all possible paths and return the one with the least gotos, i don't think there's any other way
-            // to get best output
+            // if the search budget is used up or there are no candidates, we fall back to picking edges greedily
             let mut changed = false;
             for &edge in &edges {
                 // edge might have been invalidated by a previous iteration due to insert_goto_for_edge
@@ -267,49 +382,29 @@ impl GraphStructurer {
         }
     }
 
-    fn structure(mut self) -> ast::Block {
+    fn structure(mut self) -> (ast::Block, Statistics) {
+        self.split_irreducible();
         self.collapse();
-        if self.function.graph().node_count() != 1 {
+        let mut dead_blocks = 0;
+        let mut block = if self.function.graph().node_count() != 1 {
             let mut res_block = ast::Block::default();
             let entry = self.function.entry().unwrap();
             let mut stack = vec![entry];
             let mut visited = FxHashSet::default();
+            // everything jumped to from the blocks that have been output
+            let mut successors = FxHashSet::default();
+            let mut gotos = FxHashSet::default();
             while let Some(node) = stack.pop() {
                 if visited.contains(&node) {
                     continue;
                 }
                 visited.insert(node);
 
-                fn collect_gotos(block: &ast::Block, gotos: &mut FxHashSet<ast::Label>) {
-                    for statement in &block.0 {
-                        match statement {
-                            ast::Statement::Goto(goto) => {
-                                gotos.insert(goto.0.clone());
-                            }
-                            ast::Statement::If(r#if) => {
-                                collect_gotos(&r#if.then_block.lock(), gotos);
-                                collect_gotos(&r#if.else_block.lock(), gotos);
-                            }
-                            ast::Statement::While(r#while) => {
-                                collect_gotos(&r#while.block.lock(), gotos);
-                            }
-                            ast::Statement::Repeat(repeat) => {
-                                collect_gotos(&repeat.block.lock(), gotos);
-                            }
-                            ast::Statement::NumericFor(numeric_for) => {
-                                collect_gotos(&numeric_for.block.lock(), gotos);
-                            }
-                            ast::Statement::GenericFor(generic_for) => {
-                                collect_gotos(&generic_for.block.lock(), gotos);
-                            }
-                            _ => {}
-                        }
-                    }
-                }
-
+                successors.extend(self.function.successor_blocks(node));
                 let block = self.function.remove_block(node).unwrap();
                 let mut goto_destinations = FxHashSet::default();
                 collect_gotos(&block, &mut goto_destinations);
+                gotos.extend(goto_destinations.iter().cloned());
                 for label in goto_destinations {
                     // TODO: block might have been merged/structured into another, output that block instead
                     // will require collecting label definitions in addition to references (gotos)
@@ -326,22 +421,59 @@ impl GraphStructurer {
                 }
                 if !block
                     .first()
-                    .is_some_and(|s| matches!(s, ast::Statement::Label(_)))
+                    .is_some_and(|s| matches!(s.inner(), ast::Statement::Label(_)))
                 {
                     res_block.push(ast::Comment::new(format!("block {}", node.index())).into());
                 }
                 res_block.extend(block.0)
             }
-            // TODO: these nodes are never executed (i think), comment them out or dont include them
+            // the remaining blocks can still be reached through a goto to a label that was merged
+            // into another block, or through an edge that couldn't be structured.
+            // anything else is never executed.
+            let mut dead = self
+                .function
+                .graph()
+                .node_indices()
+                .collect::<FxHashSet<_>>();
+            loop {
+                let live = dead
+                    .iter()
+                    .copied()
+                    .filter(|&node| {
+                        let mut labels = FxHashSet::default();
+                        collect_labels(self.function.block(node).unwrap(), &mut labels);
+                        successors.contains(&node) || !labels.is_disjoint(&gotos)
+                    })
+                    .collect_vec();
+                if live.is_empty() {
+                    break;
+                }
+                for node in live {
+                    dead.remove(&node);
+                    successors.extend(self.function.successor_blocks(node));
+                    collect_gotos(self.function.block(node).unwrap(), &mut gotos);
+                }
+            }
+            dead_blocks = dead.len();
+
+            let mut dead_code = ast::Block::default();
             for node in self.function.graph().node_indices().collect::<Vec<_>>() {
                 let block = self.function.remove_block(node).unwrap();
+                let output = if dead.contains(&node) {
+                    &mut dead_code
+                } else {
+                    &mut res_block
+                };
                 if !block
                     .first()
-                    .is_some_and(|s| matches!(s, ast::Statement::Label(_)))
+                    .is_some_and(|s| matches!(s.inner(), ast::Statement::Label(_)))
                 {
-                    res_block.push(ast::Comment::new(format!("block {}", node.index())).into());
+                    output.push(ast::Comment::new(format!("block {}", node.index())).into());
                 }
-                res_block.extend(block.0)
+                output.extend(block.0)
+            }
+            if self.options.emit_dead_code && !dead_code.is_empty() {
+                res_block.push(ast::Comment::new(format!("dead code\n{}", dead_code)).into());
             }
 
             res_block
@@ -351,10 +483,28 @@ impl GraphStructurer {
                     .remove_block(self.function.entry().unwrap())
                     .unwrap(),
             )
+        };
+        r#loop::simplify_loops(&mut block, self.options.allow_continue);
+        let mut block = if self.options.eliminate_gotos {
+            dispatch::eliminate_gotos(block)
+        } else {
+            block
+        };
+        // the dispatch loop continues too, so this has to come after it
+        if !self.options.allow_continue {
+            r#loop::lower_continues(&mut block);
         }
+        (block, Statistics { dead_blocks })
     }
 }
 
 pub fn lift(function: cfg::function::Function) -> ast::Block {
-    GraphStructurer::new(function).structure()
+    lift_with_options(function, Options::default()).0
+}
+
+pub fn lift_with_options(
+    function: cfg::function::Function,
+    options: Options,
+) -> (ast::Block, Statistics) {
+    GraphStructurer::new(function, options).structure()
 }
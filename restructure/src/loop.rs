@@ -20,7 +20,7 @@ impl GraphStructurer {
             .first()
             .map(|s| {
                 matches!(
-                    s,
+                    s.inner(),
                     ast::Statement::GenericForNext(_) | ast::Statement::NumForNext(_)
                 )
             })
@@ -44,10 +44,11 @@ impl GraphStructurer {
                 // TODO: REFACTOR: this is confusing
                 .find(|(_, s)| {
                     s.has_side_effects()
-                        || s.as_num_for_init().is_some()
-                        || s.as_generic_for_init().is_some()
+                        || s.inner().as_num_for_init().is_some()
+                        || s.inner().as_generic_for_init().is_some()
                 })
                 .and_then(|(i, s)| {
+                    let s = s.inner();
                     if s.as_num_for_init().is_some() || s.as_generic_for_init().is_some() {
                         Some((p, i))
                     } else {
@@ -95,7 +96,13 @@ impl GraphStructurer {
                     return false;
                 }
 
-                let statement = self.function.block_mut(header).unwrap().pop().unwrap();
+                let statement = self
+                    .function
+                    .block_mut(header)
+                    .unwrap()
+                    .pop()
+                    .unwrap()
+                    .into_inner();
                 let statements = std::mem::take(&mut self.function.block_mut(header).unwrap().0);
 
                 let body_ast = if then_node == init_block {
@@ -103,7 +110,10 @@ impl GraphStructurer {
                 } else {
                     let mut body_ast = self.function.remove_block(then_node).unwrap();
                     body_ast.extend(statements.iter().cloned());
-                    if !matches!(body_ast.last(), Some(ast::Statement::Return(_))) {
+                    if !matches!(
+                        body_ast.last().map(ast::Statement::inner),
+                        Some(ast::Statement::Return(_))
+                    ) {
                         body_ast.push(ast::Break {}.into());
                     }
                     body_ast
@@ -112,7 +122,11 @@ impl GraphStructurer {
                 init_ast.extend(statements);
                 let new_stat = match statement {
                     ast::Statement::NumForNext(num_for_next) => {
-                        let for_init = init_ast.remove(init_index).into_num_for_init().unwrap();
+                        let for_init = init_ast
+                            .remove(init_index)
+                            .into_inner()
+                            .into_num_for_init()
+                            .unwrap();
                         ast::NumericFor::new(
                             for_init.counter.1,
                             for_init.limit.1,
@@ -123,7 +137,11 @@ impl GraphStructurer {
                         .into()
                     }
                     ast::Statement::GenericForNext(generic_for_next) => {
-                        let for_init = init_ast.remove(init_index).into_generic_for_init().unwrap();
+                        let for_init = init_ast
+                            .remove(init_index)
+                            .into_inner()
+                            .into_generic_for_init()
+                            .unwrap();
                         ast::GenericFor::new(
                             generic_for_next
                                 .res_locals
@@ -212,7 +230,13 @@ impl GraphStructurer {
                     }
                     _ => unreachable!(),
                 };
-                let statement = self.function.block_mut(header).unwrap().pop().unwrap();
+                let statement = self
+                    .function
+                    .block_mut(header)
+                    .unwrap()
+                    .pop()
+                    .unwrap()
+                    .into_inner();
                 let statements = std::mem::take(&mut self.function.block_mut(header).unwrap().0);
 
                 let (init_block, init_index) = self.find_for_init(header);
@@ -222,7 +246,11 @@ impl GraphStructurer {
                 init_ast.extend(statements);
                 let new_stat = match statement {
                     ast::Statement::NumForNext(num_for_next) => {
-                        let for_init = init_ast.remove(init_index).into_num_for_init().unwrap();
+                        let for_init = init_ast
+                            .remove(init_index)
+                            .into_inner()
+                            .into_num_for_init()
+                            .unwrap();
                         ast::NumericFor::new(
                             for_init.counter.1,
                             for_init.limit.1,
@@ -233,7 +261,11 @@ impl GraphStructurer {
                         .into()
                     }
                     ast::Statement::GenericForNext(generic_for_next) => {
-                        let for_init = init_ast.remove(init_index).into_generic_for_init().unwrap();
+                        let for_init = init_ast
+                            .remove(init_index)
+                            .into_inner()
+                            .into_generic_for_init()
+                            .unwrap();
                         ast::GenericFor::new(
                             generic_for_next
                                 .res_locals
@@ -417,7 +449,13 @@ impl GraphStructurer {
             if self.function.successor_blocks(body).exactly_one().ok() == Some(header)
                 && let Some(next) = next
             {
-                let statement = self.function.block_mut(header).unwrap().pop().unwrap();
+                let statement = self
+                    .function
+                    .block_mut(header)
+                    .unwrap()
+                    .pop()
+                    .unwrap()
+                    .into_inner();
                 if let ast::Statement::If(if_stat) = statement {
                     let mut if_condition = if_stat.condition;
                     let header_else_target =
@@ -473,7 +511,11 @@ impl GraphStructurer {
                     init_ast.extend(statements);
                     let new_stat = match statement {
                         ast::Statement::NumForNext(num_for_next) => {
-                            let for_init = init_ast.remove(init_index).into_num_for_init().unwrap();
+                            let for_init = init_ast
+                                .remove(init_index)
+                                .into_inner()
+                                .into_num_for_init()
+                                .unwrap();
                             ast::NumericFor::new(
                                 for_init.counter.1,
                                 for_init.limit.1,
@@ -484,8 +526,11 @@ impl GraphStructurer {
                             .into()
                         }
                         ast::Statement::GenericForNext(generic_for_next) => {
-                            let for_init =
-                                init_ast.remove(init_index).into_generic_for_init().unwrap();
+                            let for_init = init_ast
+                                .remove(init_index)
+                                .into_inner()
+                                .into_generic_for_init()
+                                .unwrap();
                             ast::GenericFor::new(
                                 generic_for_next
                                     .res_locals
@@ -521,6 +566,11 @@ impl GraphStructurer {
                 .successor_blocks(body)
                 .exactly_one()
                 .is_ok_and(|s| s == header)
+            && self
+                .function
+                .predecessor_blocks(body)
+                .exactly_one()
+                .is_ok_and(|p| p == header)
         {
             let block = self.function.remove_block(body).unwrap();
 
@@ -538,3 +588,601 @@ impl GraphStructurer {
         }
     }
 }
+
+// `if c then break end`, returning `c`
+fn as_break_if(statement: &ast::Statement) -> Option<ast::RValue> {
+    let r#if = statement.as_if()?;
+    (r#if.else_block.lock().is_empty()
+        && matches!(r#if.then_block.lock()[..], [ast::Statement::Break(_)]))
+    .then(|| r#if.condition.clone())
+}
+
+fn is_jump(statement: &ast::Statement) -> bool {
+    matches!(
+        statement.inner(),
+        ast::Statement::Return(_)
+            | ast::Statement::Break(_)
+            | ast::Statement::Continue(_)
+            | ast::Statement::Goto(_)
+    )
+}
+
+// whether the block continues the loop it's in, not counting nested loops
+fn contains_continue(block: &ast::Block) -> bool {
+    block.iter().any(|statement| match statement.inner() {
+        ast::Statement::Continue(_) => true,
+        ast::Statement::If(r#if) => {
+            contains_continue(&r#if.then_block.lock()) || contains_continue(&r#if.else_block.lock())
+        }
+        _ => false,
+    })
+}
+
+fn not(condition: ast::RValue) -> ast::RValue {
+    ast::Unary::new(condition, ast::UnaryOperation::Not).reduce_condition()
+}
+
+// a `continue` at the end of a loop body, or at the end of a branch at the end of it, does nothing.
+// one with comments attached to it is kept, so they aren't lost
+fn remove_trailing_continue(body: &mut ast::Block) {
+    match body.last() {
+        Some(ast::Statement::Continue(_)) => {
+            body.pop();
+        }
+        Some(statement) => {
+            if let ast::Statement::If(r#if) = statement.inner() {
+                remove_trailing_continue(&mut r#if.then_block.lock());
+                remove_trailing_continue(&mut r#if.else_block.lock());
+            }
+        }
+        None => {}
+    }
+}
+
+// `if c then a else b end` at the end of a loop body becomes `if c then a continue end b`,
+// with the shorter branch as the guard. the `continue` is left out if the guard doesn't fall
+// through, otherwise the loop has to support `continue`.
+fn flatten_tail_if(body: &mut ast::Block, allow_continue: bool) {
+    while let Some(ast::Statement::If(r#if)) = body.last_mut().map(ast::Statement::inner_mut) {
+        let then_len = r#if.then_block.lock().len();
+        let else_len = r#if.else_block.lock().len();
+        if then_len == 0 || else_len == 0 || then_len == else_len {
+            return;
+        }
+        let swap = else_len < then_len;
+        let guard = if swap {
+            &r#if.else_block
+        } else {
+            &r#if.then_block
+        };
+        let falls_through = !guard.lock().last().is_some_and(is_jump);
+        if falls_through && !allow_continue {
+            return;
+        }
+        if swap {
+            std::mem::swap(&mut r#if.then_block, &mut r#if.else_block);
+            r#if.condition = not(r#if.condition.clone());
+        }
+        if falls_through {
+            r#if.then_block.lock().push(ast::Continue {}.into());
+        }
+        let rest = std::mem::take(&mut *r#if.else_block.lock());
+        body.extend(rest.0);
+    }
+}
+
+// simplifies the loops that were collapsed from the graph:
+// - exits at the start of a `while` loop are hoisted into its condition
+// - an exit at the end of an infinite loop turns it into a `repeat` loop
+// - an exit at the end of a `repeat` loop is merged into its condition
+// - branches at the end of a loop body that go back to the header become guard clauses
+pub(crate) fn simplify_loops(block: &mut ast::Block, allow_continue: bool) {
+    for statement in block.iter_mut() {
+        // a loop with comments attached to it keeps them when it's turned into a `repeat` loop
+        let statement = statement.inner_mut();
+        match statement {
+            ast::Statement::If(r#if) => {
+                simplify_loops(&mut r#if.then_block.lock(), allow_continue);
+                simplify_loops(&mut r#if.else_block.lock(), allow_continue);
+            }
+            ast::Statement::While(r#while) => {
+                let mut body = r#while.block.lock();
+                simplify_loops(&mut body, allow_continue);
+                while let Some(condition) = body.first().and_then(as_break_if) {
+                    body.remove(0);
+                    r#while.condition = match &r#while.condition {
+                        ast::RValue::Literal(ast::Literal::Boolean(true)) => not(condition),
+                        while_condition => ast::Binary::new(
+                            while_condition.clone(),
+                            not(condition),
+                            ast::BinaryOperation::And,
+                        )
+                        .into(),
+                    };
+                }
+                remove_trailing_continue(&mut body);
+                if body.len() > 1
+                    && matches!(
+                        r#while.condition,
+                        ast::RValue::Literal(ast::Literal::Boolean(true))
+                    )
+                    && let Some(condition) = body.last().and_then(as_break_if)
+                    && !contains_continue(&body)
+                {
+                    body.pop();
+                    let repeat = ast::Repeat::new(condition, std::mem::take(&mut body));
+                    drop(body);
+                    *statement = repeat.into();
+                } else {
+                    flatten_tail_if(&mut body, allow_continue);
+                }
+            }
+            ast::Statement::Repeat(repeat) => {
+                let mut body = repeat.block.lock();
+                simplify_loops(&mut body, allow_continue);
+                remove_trailing_continue(&mut body);
+                if let Some(condition) = body.last().and_then(as_break_if)
+                    && !contains_continue(&body)
+                {
+                    body.pop();
+                    repeat.condition = match &repeat.condition {
+                        ast::RValue::Literal(ast::Literal::Boolean(false)) => condition,
+                        until_condition => ast::Binary::new(
+                            condition,
+                            until_condition.clone(),
+                            ast::BinaryOperation::Or,
+                        )
+                        .into(),
+                    };
+                }
+            }
+            ast::Statement::NumericFor(numeric_for) => {
+                let mut body = numeric_for.block.lock();
+                simplify_loops(&mut body, allow_continue);
+                remove_trailing_continue(&mut body);
+                flatten_tail_if(&mut body, allow_continue);
+            }
+            ast::Statement::GenericFor(generic_for) => {
+                let mut body = generic_for.block.lock();
+                simplify_loops(&mut body, allow_continue);
+                remove_trailing_continue(&mut body);
+                flatten_tail_if(&mut body, allow_continue);
+            }
+            _ => {}
+        }
+    }
+}
+
+// whether the block leaves the loop it's in, not counting nested loops
+fn contains_break(block: &ast::Block) -> bool {
+    block.iter().any(|statement| match statement.inner() {
+        ast::Statement::Break(_) => true,
+        ast::Statement::If(r#if) => {
+            contains_break(&r#if.then_block.lock()) || contains_break(&r#if.else_block.lock())
+        }
+        _ => false,
+    })
+}
+
+// turns the `continue`s of a loop body into `break`s, setting `broke` before the `break`s
+// that were already there
+fn continue_to_break(block: &mut ast::Block, broke: Option<&ast::RcLocal>) {
+    let mut index = 0;
+    while index < block.len() {
+        let statement = block[index].inner_mut();
+        match statement {
+            ast::Statement::Continue(_) => *statement = ast::Break {}.into(),
+            ast::Statement::If(r#if) => {
+                continue_to_break(&mut r#if.then_block.lock(), broke);
+                continue_to_break(&mut r#if.else_block.lock(), broke);
+            }
+            ast::Statement::Break(_) => {
+                if let Some(broke) = broke {
+                    let assign = ast::Assign::new(
+                        vec![broke.clone().into()],
+                        vec![ast::Literal::Boolean(true).into()],
+                    );
+                    block.insert(index, assign.into());
+                    index += 1;
+                }
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+}
+
+// lowers `continue` for dialects without it. the body of a loop that continues is wrapped
+// in `repeat ... until true`, so `continue` becomes a `break` out of it. a `break` out of the
+// loop itself sets a flag that breaks again after the `repeat` loop
+pub(crate) fn lower_continues(block: &mut ast::Block) {
+    for statement in block.iter_mut() {
+        let body = match statement.inner_mut() {
+            ast::Statement::If(r#if) => {
+                lower_continues(&mut r#if.then_block.lock());
+                lower_continues(&mut r#if.else_block.lock());
+                continue;
+            }
+            ast::Statement::While(r#while) => &r#while.block,
+            ast::Statement::Repeat(repeat) => &repeat.block,
+            ast::Statement::NumericFor(numeric_for) => &numeric_for.block,
+            ast::Statement::GenericFor(generic_for) => &generic_for.block,
+            _ => continue,
+        };
+        let mut body = body.lock();
+        lower_continues(&mut body);
+        if !contains_continue(&body) {
+            continue;
+        }
+        let broke = contains_break(&body).then(ast::RcLocal::default);
+        continue_to_break(&mut body, broke.as_ref());
+        let inner = ast::Repeat::new(
+            ast::Literal::Boolean(true).into(),
+            std::mem::take(&mut body),
+        );
+        if let Some(broke) = &broke {
+            body.push(
+                ast::Assign::new(
+                    vec![broke.clone().into()],
+                    vec![ast::Literal::Boolean(false).into()],
+                )
+                .into(),
+            );
+        }
+        body.push(inner.into());
+        if let Some(broke) = broke {
+            body.push(
+                ast::If::new(
+                    broke.into(),
+                    ast::Block(vec![ast::Break {}.into()]),
+                    ast::Block::default(),
+                )
+                .into(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ast::{local_declarations::LocalDeclarer, name_locals::name_locals};
+    use parking_lot::Mutex;
+    use triomphe::Arc;
+
+    use super::*;
+
+    fn global(name: &str) -> ast::RValue {
+        ast::Global::new(name.as_bytes().to_vec()).into()
+    }
+
+    fn print(name: &str) -> ast::Statement {
+        ast::Call::new(global("print"), vec![global(name)]).into()
+    }
+
+    fn break_if(condition: &str) -> ast::Statement {
+        ast::If::new(
+            global(condition),
+            vec![ast::Break {}.into()].into(),
+            ast::Block::default(),
+        )
+        .into()
+    }
+
+    fn infinite(body: Vec<ast::Statement>) -> ast::Statement {
+        ast::While::new(ast::Literal::Boolean(true).into(), body.into()).into()
+    }
+
+    fn simplify(statement: ast::Statement) -> String {
+        simplify_with(statement, true)
+    }
+
+    fn simplify_with(statement: ast::Statement, allow_continue: bool) -> String {
+        let mut block = ast::Block(vec![statement]);
+        simplify_loops(&mut block, allow_continue);
+        block.to_string()
+    }
+
+    #[test]
+    fn leading_break_hoisted_into_while() {
+        let statement = infinite(vec![break_if("c"), print("a")]);
+        assert_eq!(simplify(statement), "while not c do\n\tprint(a)\nend");
+    }
+
+    #[test]
+    fn trailing_break_turns_while_into_repeat() {
+        let statement = infinite(vec![print("a"), print("b"), break_if("c")]);
+        assert_eq!(
+            simplify(statement),
+            "repeat\n\tprint(a)\n\tprint(b)\nuntil c"
+        );
+    }
+
+    #[test]
+    fn trailing_break_merged_into_until() {
+        let statement = ast::Repeat::new(global("d"), vec![print("a"), break_if("c")].into());
+        assert_eq!(
+            simplify(statement.into()),
+            "repeat\n\tprint(a)\nuntil c or d"
+        );
+    }
+
+    #[test]
+    fn continue_from_nested_branch() {
+        // `continue` in a `repeat` loop would skip to the condition, so the loop is kept
+        let statement = infinite(vec![
+            ast::If::new(
+                global("c"),
+                vec![print("a"), ast::Continue {}.into()].into(),
+                ast::Block::default(),
+            )
+            .into(),
+            print("b"),
+            break_if("d"),
+        ]);
+        assert_eq!(
+            simplify(statement),
+            "\
+while true do
+	if c then
+		print(a)
+		continue
+	end
+	print(b)
+	if d then
+		break
+	end
+end"
+        );
+    }
+
+    #[test]
+    fn trailing_continue_removed() {
+        let statement = infinite(vec![
+            print("a"),
+            ast::If::new(
+                global("c"),
+                vec![print("b"), ast::Continue {}.into()].into(),
+                vec![print("d"), ast::Continue {}.into()].into(),
+            )
+            .into(),
+        ]);
+        assert_eq!(
+            simplify(statement),
+            "\
+while true do
+	print(a)
+	if c then
+		print(b)
+	else
+		print(d)
+	end
+end"
+        );
+    }
+
+    #[test]
+    fn multi_exit_loop() {
+        let statement = infinite(vec![
+            break_if("a"),
+            break_if("b"),
+            print("c"),
+            break_if("d"),
+            print("e"),
+        ]);
+        assert_eq!(
+            simplify(statement),
+            "\
+while not a and not b do
+	print(c)
+	if d then
+		break
+	end
+	print(e)
+end"
+        );
+    }
+
+    #[test]
+    fn commented_loop_turns_into_repeat() {
+        let mut statement = infinite(vec![print("a"), print("b"), break_if("c")]);
+        ast::comment_before(&mut statement, ast::Comment::new("d".to_string()));
+        assert_eq!(
+            simplify(statement),
+            "-- d\nrepeat\n\tprint(a)\n\tprint(b)\nuntil c"
+        );
+    }
+
+    fn numeric_for(body: Vec<ast::Statement>) -> ast::Statement {
+        ast::NumericFor::new(
+            ast::Literal::Number(1.0).into(),
+            ast::Literal::Number(10.0).into(),
+            ast::Literal::Number(1.0).into(),
+            ast::RcLocal::new(ast::Local::new(Some("i".to_string()))),
+            body.into(),
+        )
+        .into()
+    }
+
+    #[test]
+    fn tail_if_becomes_guard_clause() {
+        let statement = numeric_for(vec![ast::If::new(
+            global("c"),
+            vec![print("a"), print("b"), print("d")].into(),
+            vec![print("e")].into(),
+        )
+        .into()]);
+        assert_eq!(
+            simplify(statement),
+            "\
+for i = 1, 10 do
+	if not c then
+		print(e)
+		continue
+	end
+	print(a)
+	print(b)
+	print(d)
+end"
+        );
+    }
+
+    #[test]
+    fn tail_if_kept_without_continue() {
+        let statement = numeric_for(vec![ast::If::new(
+            global("c"),
+            vec![print("a"), print("b")].into(),
+            vec![print("d")].into(),
+        )
+        .into()]);
+        assert_eq!(
+            simplify_with(statement, false),
+            "\
+for i = 1, 10 do
+	if c then
+		print(a)
+		print(b)
+	else
+		print(d)
+	end
+end"
+        );
+    }
+
+    #[test]
+    fn tail_if_with_exit_flattened_without_continue() {
+        let statement = numeric_for(vec![ast::If::new(
+            global("c"),
+            vec![print("a"), ast::Break {}.into()].into(),
+            vec![print("b"), print("d"), print("e")].into(),
+        )
+        .into()]);
+        assert_eq!(
+            simplify_with(statement, false),
+            "\
+for i = 1, 10 do
+	if c then
+		print(a)
+		break
+	end
+	print(b)
+	print(d)
+	print(e)
+end"
+        );
+    }
+
+    #[test]
+    fn tail_if_with_back_edge() {
+        // the back edge at the end of the body is implicit, so only the branch is left
+        let statement = ast::While::new(
+            global("x"),
+            vec![
+                print("a"),
+                ast::If::new(
+                    global("c"),
+                    vec![print("b"), ast::Continue {}.into()].into(),
+                    ast::Block::default(),
+                )
+                .into(),
+            ]
+            .into(),
+        );
+        assert_eq!(
+            simplify(statement.into()),
+            "\
+while x do
+	print(a)
+	if c then
+		print(b)
+	end
+end"
+        );
+    }
+
+    fn lower(statement: ast::Statement) -> String {
+        let mut block = ast::Block(vec![statement]);
+        lower_continues(&mut block);
+        let block = Arc::new(Mutex::new(block));
+        LocalDeclarer::default().declare_locals(block.clone(), &FxHashSet::default());
+        let mut block = Arc::try_unwrap(block).unwrap().into_inner();
+        name_locals(&mut block, false, false);
+        block.to_string()
+    }
+
+    #[test]
+    fn continue_lowered() {
+        let statement = ast::While::new(
+            global("x"),
+            vec![
+                ast::If::new(
+                    global("c"),
+                    vec![ast::Continue {}.into()].into(),
+                    ast::Block::default(),
+                )
+                .into(),
+                print("a"),
+            ]
+            .into(),
+        );
+        assert_eq!(
+            lower(statement.into()),
+            "\
+while x do
+	repeat
+		if c then
+			break
+		end
+		print(a)
+	until true
+end"
+        );
+    }
+
+    #[test]
+    fn continue_lowered_with_break() {
+        // the `break` has to leave the `while` loop too, not just the `repeat` loop around its body
+        let statement = infinite(vec![
+            ast::If::new(
+                global("c"),
+                vec![ast::Continue {}.into()].into(),
+                ast::Block::default(),
+            )
+            .into(),
+            print("a"),
+            break_if("d"),
+            ast::NumericFor::new(
+                ast::Literal::Number(1.0).into(),
+                ast::Literal::Number(10.0).into(),
+                ast::Literal::Number(1.0).into(),
+                ast::RcLocal::new(ast::Local::new(Some("i".to_string()))),
+                vec![break_if("e")].into(),
+            )
+            .into(),
+        ]);
+        assert_eq!(
+            lower(statement),
+            "\
+while true do
+	local v1 = false
+	repeat
+		if c then
+			break
+		end
+		print(a)
+		if d then
+			v1 = true
+			break
+		end
+		for i = 1, 10 do
+			if e then
+				break
+			end
+		end
+	until true
+	if v1 then
+		break
+	end
+end"
+        );
+    }
+}
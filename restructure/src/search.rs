@@ -0,0 +1,276 @@
+use cfg::function::Function;
+use itertools::Itertools;
+use parking_lot::Mutex;
+use petgraph::{
+    algo::dominators::simple_fast,
+    stable_graph::{EdgeIndex, NodeIndex},
+    visit::{Dfs, EdgeRef, IntoEdgeReferences, Walker},
+    Direction,
+};
+use rustc_hash::FxHashSet;
+use triomphe::Arc;
+
+use crate::{GraphStructurer, Options};
+
+// clones a block, including nested blocks since structuring modifies them in place
+pub(crate) fn deep_clone_block(block: &ast::Block) -> ast::Block {
+    let fork =
+        |block: &Arc<Mutex<ast::Block>>| Arc::new(Mutex::new(deep_clone_block(&block.lock())));
+    block
+        .iter()
+        .map(|statement| {
+            let mut statement = statement.clone();
+            match statement.inner_mut() {
+                ast::Statement::If(r#if) => {
+                    r#if.then_block = fork(&r#if.then_block);
+                    r#if.else_block = fork(&r#if.else_block);
+                }
+                ast::Statement::While(r#while) => r#while.block = fork(&r#while.block),
+                ast::Statement::Repeat(repeat) => repeat.block = fork(&repeat.block),
+                ast::Statement::NumericFor(numeric_for) => {
+                    numeric_for.block = fork(&numeric_for.block)
+                }
+                ast::Statement::GenericFor(generic_for) => {
+                    generic_for.block = fork(&generic_for.block)
+                }
+                _ => {}
+            }
+            statement
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+// the number of gotos and labels in a block, including nested blocks
+fn count_gotos(block: &ast::Block) -> usize {
+    block
+        .iter()
+        .map(|statement| match statement.inner() {
+            ast::Statement::Goto(_) | ast::Statement::Label(_) => 1,
+            ast::Statement::If(r#if) => {
+                count_gotos(&r#if.then_block.lock()) + count_gotos(&r#if.else_block.lock())
+            }
+            ast::Statement::While(r#while) => count_gotos(&r#while.block.lock()),
+            ast::Statement::Repeat(repeat) => count_gotos(&repeat.block.lock()),
+            ast::Statement::NumericFor(numeric_for) => count_gotos(&numeric_for.block.lock()),
+            ast::Statement::GenericFor(generic_for) => count_gotos(&generic_for.block.lock()),
+            _ => 0,
+        })
+        .sum()
+}
+
+impl GraphStructurer {
+    // a copy of `region` that can be collapsed without affecting this one, entered through `head`
+    fn fork(&self, head: NodeIndex, region: &FxHashSet<NodeIndex>, options: Options) -> Self {
+        let mut function = Function::new(self.function.id);
+        // indices are preserved, so the candidate edges can be used in the copy as well
+        *function.graph_mut() = self.function.graph().filter_map(
+            |node, block| region.contains(&node).then(|| deep_clone_block(block)),
+            |_, edge| Some(edge.clone()),
+        );
+        function.set_entry(head);
+        let mut fork = Self {
+            label_to_node: self
+                .label_to_node
+                .iter()
+                .filter(|(_, node)| region.contains(node))
+                .map(|(label, &node)| (label.clone(), node))
+                .collect(),
+            function,
+            loop_headers: FxHashSet::default(),
+            options,
+            split: self.split,
+        };
+        fork.find_loop_headers();
+        fork
+    }
+
+    // the smallest part of the graph that contains every candidate and can only be entered through
+    // its head, which is all that placing a goto changes. the head is never a for loop, since the
+    // loop can't be collapsed without the block initializing it.
+    fn region(&self, candidates: &[EdgeIndex]) -> (NodeIndex, FxHashSet<NodeIndex>) {
+        let graph = self.function.graph();
+        let entry = self.function.entry().unwrap();
+        let dominators = simple_fast(graph, entry);
+        let mut ends = candidates.iter().flat_map(|&edge| {
+            let (source, target) = graph.edge_endpoints(edge).unwrap();
+            [source, target]
+        });
+        let mut common = dominators
+            .dominators(ends.next().unwrap())
+            .unwrap()
+            .collect_vec();
+        for node in ends {
+            let node_dominators = dominators.dominators(node).unwrap().collect_vec();
+            common.retain(|d| node_dominators.contains(d));
+        }
+        let mut head = common[0];
+        loop {
+            let region = Dfs::new(graph, head).iter(graph).collect::<FxHashSet<_>>();
+            let single_entry = region.iter().all(|&node| {
+                node == head
+                    || graph
+                        .neighbors_directed(node, Direction::Incoming)
+                        .all(|p| region.contains(&p) || dominators.dominators(p).is_none())
+            });
+            if head == entry || (single_entry && !self.is_for_next(head)) {
+                return (head, region);
+            }
+            head = dominators.immediate_dominator(head).unwrap();
+        }
+    }
+
+    fn goto_count(&self) -> usize {
+        self.function
+            .blocks()
+            .map(|(_, block)| count_gotos(block))
+            .sum()
+    }
+
+    // the edges that can be replaced with a goto without making part of the graph unreachable.
+    // https://edmcman.github.io/papers/usenix13.pdf
+    // these are the edges whose source does not dominate its target, nor whose target dominates its source
+    fn goto_candidates(&self) -> Vec<EdgeIndex> {
+        let dominators = simple_fast(self.function.graph(), self.function.entry().unwrap());
+        let dominates = |a: NodeIndex, b: NodeIndex| {
+            dominators
+                .dominators(b)
+                .is_some_and(|mut dominators| dominators.any(|d| d == a))
+        };
+        self.function
+            .graph()
+            .edge_references()
+            .filter(|edge| {
+                // TODO: check if blocks in dfs instead
+                dominators.dominators(edge.source()).is_some()
+                    && dominators.dominators(edge.target()).is_some()
+                    && !dominates(edge.source(), edge.target())
+                    && !dominates(edge.target(), edge.source())
+            })
+            .map(|edge| edge.id())
+            .collect()
+    }
+
+    // tries replacing each candidate edge with a goto and collapsing the rest of the graph greedily,
+    // returning the edge that results in the least gotos and labels.
+    // every attempt uses up one unit of the search budget.
+    pub(crate) fn search_goto_edge(&mut self) -> Option<EdgeIndex> {
+        let candidates = self.goto_candidates();
+        if candidates.is_empty() || self.options.goto_search_budget == 0 {
+            return None;
+        }
+        let (head, region) = self.region(&candidates);
+        let mut best: Option<(EdgeIndex, usize)> = None;
+        for edge in candidates {
+            if self.options.goto_search_budget == 0 {
+                break;
+            }
+            self.options.goto_search_budget -= 1;
+
+            let mut options = self.options.clone();
+            options.goto_search_budget = 0;
+            let mut attempt = self.fork(head, &region, options);
+            attempt.insert_goto_for_edge(edge);
+            attempt.find_loop_headers();
+            attempt.collapse();
+            let cost = attempt.goto_count();
+            if best.is_none_or(|(_, best_cost)| cost < best_cost) {
+                best = Some((edge, cost));
+            }
+        }
+        best.map(|(edge, _)| edge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cfg::block::{BlockEdge, BranchType};
+
+    use super::*;
+
+    fn global(name: &str) -> ast::RValue {
+        ast::Global::new(name.as_bytes().to_vec()).into()
+    }
+
+    fn call(name: &str) -> ast::Statement {
+        ast::Call::new(global(name), vec![]).into()
+    }
+
+    fn branch(condition: &str) -> ast::Statement {
+        ast::If::new(global(condition), Default::default(), Default::default()).into()
+    }
+
+    fn conditional(then_target: NodeIndex, else_target: NodeIndex) -> Vec<(NodeIndex, BlockEdge)> {
+        vec![
+            (then_target, BlockEdge::new(BranchType::Then)),
+            (else_target, BlockEdge::new(BranchType::Else)),
+        ]
+    }
+
+    // a loop between `a` and `b` that can be entered through either of them, after `start`
+    fn structurer(goto_search_budget: usize) -> (GraphStructurer, [NodeIndex; 5]) {
+        let mut function = Function::new(0);
+        let start = function.new_block();
+        let entry = function.new_block();
+        let a = function.new_block();
+        let b = function.new_block();
+        let exit = function.new_block();
+        function.set_entry(start);
+        function.block_mut(start).unwrap().push(call("start"));
+        function.block_mut(entry).unwrap().push(branch("c"));
+        function
+            .block_mut(a)
+            .unwrap()
+            .extend([call("a"), branch("ca")]);
+        function
+            .block_mut(b)
+            .unwrap()
+            .extend([call("b"), branch("cb")]);
+        function
+            .block_mut(exit)
+            .unwrap()
+            .extend([call("exit"), ast::Return::new(Vec::new()).into()]);
+        function.set_edges(
+            start,
+            vec![(entry, BlockEdge::new(BranchType::Unconditional))],
+        );
+        function.set_edges(entry, conditional(a, b));
+        function.set_edges(a, conditional(b, exit));
+        function.set_edges(b, conditional(a, exit));
+        let options = Options {
+            split_threshold: 0,
+            goto_search_budget,
+            ..Default::default()
+        };
+        (
+            GraphStructurer::new(function, options),
+            [start, entry, a, b, exit],
+        )
+    }
+
+    #[test]
+    fn region_starts_at_common_dominator() {
+        let (structurer, [_, entry, a, b, exit]) = structurer(32);
+        let candidates = structurer.goto_candidates();
+        assert_eq!(candidates.len(), 4);
+        let (head, region) = structurer.region(&candidates);
+        assert_eq!(head, entry);
+        assert_eq!(region, FxHashSet::from_iter([entry, a, b, exit]));
+    }
+
+    #[test]
+    fn search_uses_up_budget() {
+        let (mut structurer, _) = structurer(2);
+        assert!(structurer.search_goto_edge().is_some());
+        assert_eq!(structurer.options.goto_search_budget, 0);
+        assert!(structurer.search_goto_edge().is_none());
+    }
+
+    #[test]
+    fn attempts_do_not_search() {
+        let (mut structurer, _) = structurer(32);
+        assert!(structurer.search_goto_edge().is_some());
+        // one attempt per candidate, collapsing an attempt doesn't search any further
+        assert_eq!(structurer.options.goto_search_budget, 28);
+    }
+}
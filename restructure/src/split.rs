@@ -0,0 +1,251 @@
+use itertools::Itertools;
+use petgraph::{
+    algo::tarjan_scc,
+    stable_graph::NodeIndex,
+    visit::{Dfs, EdgeRef, NodeFiltered, Walker},
+    Direction,
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::{search::deep_clone_block, GraphStructurer};
+
+// controlled node splitting.
+// an irreducible loop is a strongly connected region that can be entered through more than one node.
+// every entry but the header is duplicated for the predecessors outside of the region, which moves
+// the entry further into the region. this is repeated until the header is the only entry left.
+// only blocks within the size threshold are duplicated, anything else is left to goto insertion.
+impl GraphStructurer {
+    fn can_split(&self, node: NodeIndex) -> bool {
+        let block = self.function.block(node).unwrap();
+        block.len() <= self.options.split_threshold
+            && !block.iter().any(|s| {
+                matches!(
+                    s.inner(),
+                    ast::Statement::NumForInit(_)
+                        | ast::Statement::NumForNext(_)
+                        | ast::Statement::GenericForInit(_)
+                        | ast::Statement::GenericForNext(_)
+                )
+            })
+    }
+
+    // duplicates `node` and redirects the edges coming from `predecessors` to the copy
+    fn split_node(&mut self, node: NodeIndex, predecessors: &FxHashSet<NodeIndex>) {
+        let copy = self.function.new_block();
+        *self.function.block_mut(copy).unwrap() =
+            deep_clone_block(self.function.block(node).unwrap());
+        let edges = self
+            .function
+            .edges(node)
+            .map(|e| (e.target(), e.weight().clone()))
+            .collect_vec();
+        self.function.set_edges(copy, edges);
+
+        let incoming = self
+            .function
+            .graph()
+            .edges_directed(node, Direction::Incoming)
+            .filter(|e| predecessors.contains(&e.source()))
+            .map(|e| e.id())
+            .collect_vec();
+        for edge in incoming {
+            let (source, _) = self.function.graph().edge_endpoints(edge).unwrap();
+            let weight = self.function.graph_mut().remove_edge(edge).unwrap();
+            self.function.graph_mut().add_edge(source, copy, weight);
+        }
+    }
+
+    // the first loop with more than one entry that can be split. nested loops are searched by
+    // removing the header of a reducible loop and looking for loops in what remains.
+    // returns the loop and its entries in depth first order, the first entry being the header.
+    fn find_irreducible_loop(
+        &self,
+        order: &FxHashMap<NodeIndex, usize>,
+    ) -> Option<(FxHashSet<NodeIndex>, Vec<NodeIndex>)> {
+        let graph = self.function.graph();
+        let entry = self.function.entry().unwrap();
+        let mut regions = vec![order.keys().copied().collect::<FxHashSet<_>>()];
+        while let Some(region) = regions.pop() {
+            let subgraph = NodeFiltered::from_fn(graph, |n| region.contains(&n));
+            for scc in tarjan_scc(&subgraph) {
+                if scc.len() < 2 {
+                    continue;
+                }
+                let scc = scc.into_iter().collect::<FxHashSet<_>>();
+                let entries = scc
+                    .iter()
+                    .copied()
+                    .filter(|&n| {
+                        n == entry
+                            || self
+                                .function
+                                .predecessor_blocks(n)
+                                .any(|p| order.contains_key(&p) && !scc.contains(&p))
+                    })
+                    .sorted_by_key(|n| order[n])
+                    .collect_vec();
+                if entries.len() > 1 {
+                    // loops that cannot be split are left to goto insertion
+                    if entries[1..].iter().any(|&n| self.can_split(n)) {
+                        return Some((scc, entries));
+                    }
+                    continue;
+                }
+                let mut body = scc;
+                body.remove(&entries[0]);
+                regions.push(body);
+            }
+        }
+        None
+    }
+
+    pub(crate) fn split_irreducible(&mut self) {
+        if self.options.split_threshold == 0 {
+            return;
+        }
+        let entry = self.function.entry().unwrap();
+        // every split adds a block, so bound the amount of splits to keep the graph from blowing up
+        let mut budget = self.function.graph().node_count();
+        while budget > 0 {
+            let order = Dfs::new(self.function.graph(), entry)
+                .iter(self.function.graph())
+                .enumerate()
+                .map(|(i, n)| (n, i))
+                .collect::<FxHashMap<_, _>>();
+            let Some((scc, entries)) = self.find_irreducible_loop(&order) else {
+                break;
+            };
+
+            for node in entries.into_iter().skip(1) {
+                if budget == 0 || !self.can_split(node) {
+                    continue;
+                }
+                let predecessors = self
+                    .function
+                    .predecessor_blocks(node)
+                    .filter(|p| order.contains_key(p) && !scc.contains(p))
+                    .collect::<FxHashSet<_>>();
+                self.split_node(node, &predecessors);
+                self.split = true;
+                budget -= 1;
+            }
+        }
+        if self.split {
+            self.find_loop_headers();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cfg::{
+        block::{BlockEdge, BranchType},
+        function::Function,
+    };
+
+    use super::*;
+    use crate::Options;
+
+    fn global(name: &str) -> ast::RValue {
+        ast::Global::new(name.as_bytes().to_vec()).into()
+    }
+
+    fn call(name: &str) -> ast::Statement {
+        ast::Call::new(global(name), vec![]).into()
+    }
+
+    fn branch(condition: &str) -> ast::Statement {
+        ast::If::new(global(condition), Default::default(), Default::default()).into()
+    }
+
+    fn conditional(then_target: NodeIndex, else_target: NodeIndex) -> Vec<(NodeIndex, BlockEdge)> {
+        vec![
+            (then_target, BlockEdge::new(BranchType::Then)),
+            (else_target, BlockEdge::new(BranchType::Else)),
+        ]
+    }
+
+    // a loop between `a` and `b` that can be entered through either of them
+    fn irreducible() -> Function {
+        let mut function = Function::new(0);
+        let entry = function.new_block();
+        let a = function.new_block();
+        let b = function.new_block();
+        let exit = function.new_block();
+        function.set_entry(entry);
+        function
+            .block_mut(entry)
+            .unwrap()
+            .extend([call("start"), branch("c")]);
+        function
+            .block_mut(a)
+            .unwrap()
+            .extend([call("a"), branch("ca")]);
+        function
+            .block_mut(b)
+            .unwrap()
+            .extend([call("b"), branch("cb")]);
+        function
+            .block_mut(exit)
+            .unwrap()
+            .extend([call("exit"), ast::Return::new(Vec::new()).into()]);
+        function.set_edges(entry, conditional(a, b));
+        function.set_edges(a, conditional(b, exit));
+        function.set_edges(b, conditional(a, exit));
+        function
+    }
+
+    fn split(split_threshold: usize) -> GraphStructurer {
+        let mut structurer = GraphStructurer::new(
+            irreducible(),
+            Options {
+                split_threshold,
+                ..Default::default()
+            },
+        );
+        structurer.split_irreducible();
+        structurer
+    }
+
+    fn lift(split_threshold: usize) -> String {
+        let options = Options {
+            split_threshold,
+            ..Default::default()
+        };
+        crate::lift_with_options(irreducible(), options)
+            .0
+            .to_string()
+    }
+
+    #[test]
+    fn irreducible_loop_split() {
+        let structurer = split(8);
+        assert!(structurer.split);
+        assert_eq!(structurer.function.graph().node_count(), 5);
+        let order = Dfs::new(
+            structurer.function.graph(),
+            structurer.function.entry().unwrap(),
+        )
+        .iter(structurer.function.graph())
+        .enumerate()
+        .map(|(i, n)| (n, i))
+        .collect::<FxHashMap<_, _>>();
+        assert!(structurer.find_irreducible_loop(&order).is_none());
+        // the loop is only entered through `a`, `b` is duplicated for the path that skips it
+        assert_eq!(
+            lift(8),
+            "start()\nif not c then\n\tb()\n\tif not cb then\n\t\t::l3::\n\t\texit()\n\t\treturn\n\tend\nend\nrepeat\n\ta()\n\tif not ca then\n\t\tbreak\n\tend\n\tb()\nuntil not cb\ngoto l3"
+        );
+    }
+
+    #[test]
+    fn irreducible_loop_over_threshold() {
+        let structurer = split(1);
+        assert!(!structurer.split);
+        assert_eq!(structurer.function.graph().node_count(), 4);
+        assert_eq!(
+            lift(1),
+            "start()\nif not c then\n\t::l2::\n\tb()\n\tif not cb then\n\t\t::l3::\n\t\texit()\n\t\treturn\n\tend\nend\na()\nif ca then\n\tgoto l2\nend\ngoto l3"
+        );
+    }
+}
@@ -1,13 +1,37 @@
 /// NOTE: This is untested!
 
-use std::io;
+use std::{
+    io,
+    num::NonZeroUsize,
+    ops::RangeInclusive,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
-use axum::{body::{Body, Bytes}, http::StatusCode, response::{IntoResponse, Response}, routing::post, Router};
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
 use base64::prelude::*;
+use lru::LruCache;
+use luau_lifter::{
+    deserializer::bytecode::{SUPPORTED_TYPES_VERSIONS, SUPPORTED_VERSIONS},
+    Options,
+};
+use metrics::{Metrics, Outcome};
+use serde::Serialize;
 use tokio::net::TcpListener;
 use tracing::info;
 
+mod metrics;
+
 const BIND_ADDR: &'static str = "127.0.0.1:3000";
+/// Maximum number of decompilations kept in the cache.
+const CACHE_CAPACITY: NonZeroUsize = NonZeroUsize::new(256).unwrap();
 
 #[derive(Debug, thiserror::Error)]
 enum Error {
@@ -15,14 +39,23 @@ enum Error {
     Io(#[from] io::Error),
     #[error("invalid base64 data recieved: {0}")]
     Base64(#[from] base64::DecodeError),
+    #[error("the decompilation task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
 }
 impl Error {
     fn status_code(&self) -> StatusCode {
         match self {
-            Error::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Io(_) | Error::Task(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Error::Base64(_) => StatusCode::BAD_REQUEST,
         }
     }
+
+    fn outcome(&self) -> Outcome {
+        match self {
+            Error::Io(_) | Error::Task(_) => Outcome::Error,
+            Error::Base64(_) => Outcome::BadRequest,
+        }
+    }
 }
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
@@ -33,6 +66,21 @@ impl IntoResponse for Error {
     }
 }
 
+struct AppState {
+    metrics: Metrics,
+    // (bytecode, options) -> decompiled source, the least recently used entry is evicted when full
+    cache: Mutex<LruCache<(Vec<u8>, Options), Arc<str>>>,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self {
+            metrics: Metrics::default(),
+            cache: Mutex::new(LruCache::new(CACHE_CAPACITY)),
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), io::Error> {
     // Setup the logger
@@ -47,7 +95,11 @@ async fn main() -> Result<(), io::Error> {
 
     // Build our application with a route
     let app = Router::new()
-        .route("/decompile", post(decompile));
+        .route("/decompile", post(decompile))
+        .route("/healthz", get(healthz))
+        .route("/version", get(version))
+        .route("/metrics", get(metrics))
+        .with_state(Arc::new(AppState::default()));
 
     // Run the web server
     let listener = TcpListener::bind(BIND_ADDR).await?;
@@ -55,10 +107,77 @@ async fn main() -> Result<(), io::Error> {
     axum::serve(listener, app).await
 }
 
-async fn decompile(body: Bytes) -> Result<String, Error> {
+async fn decompile(
+    State(state): State<Arc<AppState>>,
+    Query(options): Query<Options>,
+    body: Bytes,
+) -> Result<String, Error> {
+    let start = Instant::now();
+    // decompiling is CPU bound, so it runs off of the async worker threads
+    let task_state = state.clone();
+    let result = tokio::task::spawn_blocking(move || decompile_cached(&task_state, body, options))
+        .await
+        .unwrap_or_else(|err| Err(err.into()));
+    let outcome = match &result {
+        Ok(_) => Outcome::Success,
+        Err(err) => err.outcome(),
+    };
+    state.metrics.record_request(outcome, start.elapsed());
+    result
+}
+
+fn decompile_cached(state: &AppState, body: Bytes, options: Options) -> Result<String, Error> {
     let mut bytecode = Vec::new();
     BASE64_STANDARD.decode_vec(body, &mut bytecode)?;
-    let decompiled = luau_lifter::decompile_bytecode(&bytecode, 203);
+
+    let key = (bytecode, options);
+    let cached = state.cache.lock().unwrap().get(&key).cloned();
+    state.metrics.record_cache(cached.is_some());
+    if let Some(decompiled) = cached {
+        info!("Served decompilation from cache.");
+        return Ok(decompiled.to_string());
+    }
+
+    let (bytecode, options) = &key;
+    let (decompiled, statistics) =
+        luau_lifter::decompile_bytecode_with_statistics(bytecode, 203, options);
+    for &stage in &statistics.failures {
+        state.metrics.record_failure(stage);
+    }
+    state.metrics.record_dead_blocks(statistics.dead_blocks);
+    if statistics.failures.is_empty() {
+        state
+            .cache
+            .lock()
+            .unwrap()
+            .put(key, decompiled.as_str().into());
+    }
     info!("Successfully decompiled bytecode.");
     Ok(decompiled)
 }
+
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+#[derive(Serialize)]
+struct Version {
+    version: &'static str,
+    bytecode_versions: RangeInclusive<u8>,
+    types_versions: RangeInclusive<u8>,
+}
+
+async fn version() -> Json<Version> {
+    Json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        bytecode_versions: SUPPORTED_VERSIONS,
+        types_versions: SUPPORTED_TYPES_VERSIONS,
+    })
+}
+
+async fn metrics(State(state): State<Arc<AppState>>) -> Response {
+    match state.metrics.render() {
+        Ok(body) => ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
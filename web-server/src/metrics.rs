@@ -0,0 +1,176 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use luau_lifter::Stage;
+
+/// Upper bounds (in seconds) of the latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|&le| seconds <= le) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str) -> std::fmt::Result {
+        let mut cumulative = 0;
+        for (le, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            writeln!(out, "{name}_bucket{{le=\"{le}\"}} {cumulative}")?;
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}")?;
+        writeln!(
+            out,
+            "{name}_sum {}",
+            self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+        )?;
+        writeln!(out, "{name}_count {count}")
+    }
+}
+
+/// The outcome of a request to `/decompile`.
+#[derive(Debug, Clone, Copy)]
+pub enum Outcome {
+    Success,
+    BadRequest,
+    Error,
+}
+
+impl Outcome {
+    const ALL: [Outcome; 3] = [Outcome::Success, Outcome::BadRequest, Outcome::Error];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::BadRequest => "bad_request",
+            Outcome::Error => "error",
+        }
+    }
+}
+
+/// Counters exposed at `/metrics` in the Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    requests: [AtomicU64; Outcome::ALL.len()],
+    latency: Histogram,
+    failures: [AtomicU64; Stage::ALL.len()],
+    dead_blocks: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+impl Metrics {
+    pub fn record_request(&self, outcome: Outcome, duration: Duration) {
+        self.requests[outcome as usize].fetch_add(1, Ordering::Relaxed);
+        self.latency.observe(duration);
+    }
+
+    pub fn record_failure(&self, stage: Stage) {
+        self.failures[stage as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_dead_blocks(&self, count: usize) {
+        self.dead_blocks.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_cache(&self, hit: bool) {
+        let counter = if hit {
+            &self.cache_hits
+        } else {
+            &self.cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> Result<String, std::fmt::Error> {
+        let mut out = String::new();
+
+        writeln!(
+            out,
+            "# HELP medal_decompile_requests_total Requests to /decompile."
+        )?;
+        writeln!(out, "# TYPE medal_decompile_requests_total counter")?;
+        for outcome in Outcome::ALL {
+            writeln!(
+                out,
+                "medal_decompile_requests_total{{outcome=\"{}\"}} {}",
+                outcome.name(),
+                self.requests[outcome as usize].load(Ordering::Relaxed)
+            )?;
+        }
+
+        writeln!(
+            out,
+            "# HELP medal_decompile_duration_seconds Time taken to handle requests to /decompile."
+        )?;
+        writeln!(out, "# TYPE medal_decompile_duration_seconds histogram")?;
+        self.latency
+            .render(&mut out, "medal_decompile_duration_seconds")?;
+
+        writeln!(
+            out,
+            "# HELP medal_decompile_failures_total Functions that failed to decompile, by stage."
+        )?;
+        writeln!(out, "# TYPE medal_decompile_failures_total counter")?;
+        for stage in Stage::ALL {
+            writeln!(
+                out,
+                "medal_decompile_failures_total{{stage=\"{}\"}} {}",
+                stage.name(),
+                self.failures[stage as usize].load(Ordering::Relaxed)
+            )?;
+        }
+
+        writeln!(
+            out,
+            "# HELP medal_dead_blocks_removed_total Unreachable blocks removed while structuring."
+        )?;
+        writeln!(out, "# TYPE medal_dead_blocks_removed_total counter")?;
+        writeln!(
+            out,
+            "medal_dead_blocks_removed_total {}",
+            self.dead_blocks.load(Ordering::Relaxed)
+        )?;
+
+        writeln!(
+            out,
+            "# HELP medal_cache_hits_total Decompilations served from the cache."
+        )?;
+        writeln!(out, "# TYPE medal_cache_hits_total counter")?;
+        writeln!(
+            out,
+            "medal_cache_hits_total {}",
+            self.cache_hits.load(Ordering::Relaxed)
+        )?;
+        writeln!(
+            out,
+            "# HELP medal_cache_misses_total Decompilations not found in the cache."
+        )?;
+        writeln!(out, "# TYPE medal_cache_misses_total counter")?;
+        writeln!(
+            out,
+            "medal_cache_misses_total {}",
+            self.cache_misses.load(Ordering::Relaxed)
+        )?;
+
+        Ok(out)
+    }
+}